@@ -0,0 +1,2 @@
+let a = 20i;
+a * 2 + 2;
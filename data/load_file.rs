@@ -0,0 +1,3 @@
+fn loaded() -> int { 42 }
+
+println!("loaded");
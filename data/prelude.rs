@@ -0,0 +1,5 @@
+use std::collections::HashMap;
+
+fn prelude_map() -> HashMap<int, int> {
+    HashMap::new()
+}
@@ -0,0 +1,2 @@
+.opt 2
+println!("config says hi");
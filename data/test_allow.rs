@@ -0,0 +1,4 @@
+.deny unused_variables
+{ let x = 1i; 3i }
+{ let x = 1i; 4i }
+.allow foo-bar
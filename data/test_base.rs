@@ -0,0 +1,13 @@
+fn double(x: int) -> int { x * 2 }
+double(21)
+.type double(1)
+fn quad(x: int) -> int { double(double(x)) }
+quad(2)
+.type quad(1)
+fn double(x: int) -> int { x * 3 }
+quad(2)
+.undo
+quad(2)
+.reset items
+fn greet(name: &str) -> String { format!("hello, {}", name) }
+greet("world")
@@ -0,0 +1,5 @@
+.cd data
+.load load_file.rs
+loaded()
+.cd nonexistent
+.pwd
@@ -0,0 +1,9 @@
+.cfg
+.cfg foo
+#[cfg(foo)] fn foo() -> int { 1 }
+foo()
+.cfg feature="bar"
+.cfg
+.cfg foo
+.cfg
+.cfg foo=bar
@@ -0,0 +1,7 @@
+.debug
+undefined_name
+.debug on
+undefined_name
+.debug
+.debug yes
+.debug
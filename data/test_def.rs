@@ -0,0 +1,15 @@
+fn foo() -> int { 1 }
+
+struct Bar;
+
+impl Clone for Bar {
+    fn clone(&self) -> Bar { Bar }
+}
+
+impl Bar {
+    fn bar(&self) {}
+}
+
+.def foo
+.def Bar
+.def baz
@@ -0,0 +1,12 @@
+use std::mem::swap;
+fn foo() -> int { 1 }
+let a = 2i;
+.def swap
+.def foo
+.def a
+.def b
+.search swap
+.search 2i
+.rm-import foo
+.rm-import swap
+.def swap
@@ -0,0 +1,3 @@
+struct Foo;
+.doc Foo
+.doc std::mem::swap
@@ -0,0 +1,5 @@
+fn one() -> int { 1 }
+.reload
+.gc
+.gc
+one()
@@ -0,0 +1,8 @@
+.get FOO
+#[no_mangle] pub static FOO: int = 12345;
+.get FOO
+#[no_mangle] pub static BAR: [u8, ..3] = [1, 2, 3];
+.get BAR
+#[no_mangle] pub static mut COUNTER: int = 1;
+.pin unsafe { COUNTER = 5; }
+.get COUNTER
@@ -0,0 +1,16 @@
+struct Foo {
+    a: int,
+}
+
+impl Foo {
+    fn new() -> Foo {
+        Foo{ a: 1 }
+    }
+
+    fn get(&self) -> int {
+        self.a
+.clone()
+    }
+}
+
+Foo::new().get()
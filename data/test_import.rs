@@ -0,0 +1,7 @@
+.import std::collections::HashMap
+HashMap::<int, int>::new().len()
+.import std::num::*
+zero::<int>() == 0
+.def num
+.def HashMap
+.import foo bar
@@ -0,0 +1,5 @@
+use std::mem::swap;
+use std::ptr::swap;
+.rm-import replace
+.rm-import swap
+use std::ptr::swap;
@@ -0,0 +1,5 @@
+let a = 1i;
+.type a
+let b = { println!("side effect"); 2i };
+a + 1
+.type a
@@ -0,0 +1,8 @@
+fn next(n: &mut int) -> int { println!("called"); *n += 1; *n }
+let mut n = 0i;
+let x = 1i;
+.type x
+let x = next(&mut n);
+.type x
+let (a, b) = (next(&mut n), 0i);
+n
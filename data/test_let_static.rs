@@ -0,0 +1,18 @@
+.let counter = 0i
+*counter += 1;
+*counter += 1;
+counter
+.type counter
+.let counter = 5
+counter
+.let flag: bool = true
+flag
+.type flag
+.let same: bool = 1i == 1
+same
+.let x
+.let s = Some(1u8)
+.let limit: ::std::option::Option<u8> = None
+limit.is_none()
+.undo
+.def limit
@@ -0,0 +1,7 @@
+.lints
+.lints unused_imports
+.lints
+.lints dead_code
+.lints
+fn unused_fn() {}
+.lints unused-imports
@@ -0,0 +1,2 @@
+.load data/load_file.rs
+loaded()
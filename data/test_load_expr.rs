@@ -0,0 +1,2 @@
+.load-expr data/load_expr_file.rs
+.load-expr
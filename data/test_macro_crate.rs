@@ -0,0 +1,8 @@
+#![feature(phase)]
+extern crate collections;
+#[phase(plugin, link)]
+extern crate log;
+
+fn foo() -> bool { log_enabled!(::log::DEBUG) }
+foo()
+.print
@@ -0,0 +1,6 @@
+#[no_mangle] pub fn unpinned() {}
+.which unpinned
+.pin 1 + 1
+.pin #[no_mangle] pub fn pinned() {}
+.gc
+.which pinned
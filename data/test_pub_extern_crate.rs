@@ -0,0 +1,4 @@
+pub extern crate "collections" as coll;
+use coll::vec::Vec;
+Vec::<int>::new().len()
+coll::vec::Vec::<int>::new().len()
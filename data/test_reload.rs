@@ -0,0 +1,6 @@
+#[no_mangle] pub fn foo() -> int { 2 }
+.reload
+.which foo
+.reload
+.which foo
+foo()
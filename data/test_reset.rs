@@ -0,0 +1,7 @@
+fn foo() -> int { 1 }
+foo()
+
+.reset
+
+fn foo() -> int { 2 }
+foo()
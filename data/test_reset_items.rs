@@ -0,0 +1,19 @@
+use std::mem::swap;
+fn foo() -> int { 1 }
+let a = 3i;
+let f = foo;
+let g = f;
+
+.reset items
+
+fn foo() -> int { 2 }
+foo() + a
+
+.reset bindings
+
+let mut a = 4i;
+let mut b = 5i;
+swap(&mut b, &mut a);
+a
+
+.reset foo
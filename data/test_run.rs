@@ -0,0 +1,11 @@
+fn answer() -> int { 42 }
+fn hello() { println!("hello"); }
+fn add(a: int, b: int) -> int { a + b }
+unsafe fn danger() -> int { 7 }
+fn nothing() -> () {}
+.run answer
+.run hello()
+.run danger
+.run nothing
+.run add
+.run missing
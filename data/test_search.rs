@@ -0,0 +1,7 @@
+use std::collections::HashMap;
+fn make_map() -> HashMap<int, int> { HashMap::new() }
+fn foo() {}
+
+.search hashmap
+.search nothing
+.se MAKE_MAP
@@ -0,0 +1,6 @@
+.expr 1i + 2;
+.stmt 1i + 2
+.expr
+let a = 1i;
+.expr
+2i + 2;
@@ -0,0 +1,8 @@
+.timeout
+.timeout 5s
+.timeout
+.timeout 250ms
+.timeout
+.timeout off
+.timeout
+.timeout soon
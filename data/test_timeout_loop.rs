@@ -0,0 +1,3 @@
+.timeout 200ms
+println!("before"); loop {}
+println!("after");
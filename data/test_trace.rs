@@ -0,0 +1,6 @@
+.trace
+.trace on
+.trace
+1i
+.trace off
+.trace
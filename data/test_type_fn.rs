@@ -0,0 +1,6 @@
+fn compute(x: int, (a, b): (uint, uint)) -> int { x }
+fn hello() {}
+unsafe extern "C" fn raw() -> i32 { 0 }
+.type compute
+.type hello
+.type raw
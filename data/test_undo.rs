@@ -0,0 +1,7 @@
+fn foo() -> int { 1 }
+foo()
+.undo
+fn foo() -> int { 2 }
+foo()
+.undo
+.undo
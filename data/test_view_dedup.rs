@@ -0,0 +1,5 @@
+extern crate collections;
+use std::mem::swap;
+extern  crate collections;
+use std::mem::swap;
+.print
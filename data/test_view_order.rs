@@ -0,0 +1,4 @@
+use std::mem::replace;
+extern crate collections;
+use std::mem::swap;
+.print
@@ -0,0 +1,3 @@
+.which foo
+.pin #[no_mangle] pub fn foo() {}
+.which foo
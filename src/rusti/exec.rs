@@ -9,24 +9,35 @@
 //! Rust code parsing and compilation.
 
 extern crate rustc_driver;
+extern crate time;
 
+use std::any::AnyRefExt;
 use std::c_str::CString;
+use std::cell::RefCell;
+use std::dynamic_lib::DynamicLibrary;
+use std::io::{ChanReader, ChanWriter};
 use std::io::fs::PathExtensions;
+use std::io::process::Command;
 use std::io::util::NullWriter;
-use std::mem::transmute;
-use std::os::{getenv_as_bytes, split_paths};
+use std::mem::{replace, size_of, transmute, transmute_copy};
+use std::os::{getenv, getenv_as_bytes, split_paths};
+use std::str;
 use std::thread::Builder;
+use std::time::Duration;
 
 use super::rustc;
 use super::rustc::llvm;
 use super::rustc::metadata::cstore::RequireDynamic;
 use super::rustc::middle::ty;
 use super::rustc::session::config::{mod, basic_options, build_configuration, Options};
+use super::rustc::session::config::OptLevel;
 use super::rustc::session::config::Input;
-use super::rustc::session::build_session;
+use super::rustc::session::{build_session, build_session_, Session};
 use self::rustc_driver::driver;
 
 use super::syntax::ast_map;
+use super::syntax::codemap::{CodeMap, Span};
+use super::syntax::diagnostic::{mod, Emitter, EmitterWriter, Level, RenderSpan};
 use super::syntax::diagnostics::registry::Registry;
 
 // This seems like a such a simple solution that I'm surprised it works.
@@ -42,52 +53,224 @@ fn morestack_addr() -> *const () {
 /// Compiles input code into an execution environment.
 pub struct ExecutionEngine {
     ee: llvm::ExecutionEngineRef,
-    modules: Vec<llvm::ModuleRef>,
+    modules: Vec<Module>,
     /// Additional search paths for libraries
     lib_paths: Vec<String>,
     sysroot: Path,
+    /// Optimization level of compiled code
+    opt_level: OptLevel,
+    /// Additional configuration flags; e.g. `foo` or `feature="foo"`
+    cfgs: Vec<String>,
+    /// Maximum number of modules to keep, if any
+    module_limit: Option<uint>,
+    /// Target triple of analyzed code, if it differs from the host.
+    /// Code compiled for another target cannot be run.
+    target: Option<String>,
+    /// Whether to record time spent in each phase of compilation
+    trace: bool,
+    /// Traces recorded and not yet taken by `take_traces`
+    traces: RefCell<Vec<Trace>>,
+}
+
+/// Time spent in each phase of a compilation, recorded when tracing
+/// is enabled
+#[deriving(Clone, Show)]
+pub struct Trace {
+    /// Kind of compilation; `"compile"` or `"analysis"`
+    pub what: &'static str,
+    /// Name of each phase which began and the time spent in it,
+    /// or `None` if the phase did not finish
+    pub phases: Vec<(&'static str, Option<Duration>)>,
+}
+
+/// Module contained within an `ExecutionEngine`
+struct Module {
+    llmod: llvm::ModuleRef,
+    /// Whether code within the module may still be running; e.g. in a thread
+    /// spawned by module code. A module is assumed to be running until it
+    /// is marked as finished.
+    running: bool,
+    /// Whether the module is kept even when finished, so that its symbols
+    /// remain available. A pinned module is removed only by `remove_module`.
+    pinned: bool,
+}
+
+impl Module {
+    fn new(llmod: llvm::ModuleRef) -> Module {
+        Module{
+            llmod: llmod,
+            running: true,
+            pinned: false,
+        }
+    }
+}
+
+/// Description of a module contained within an `ExecutionEngine`
+#[deriving(Clone, PartialEq, Eq, Show)]
+pub struct ModuleInfo {
+    /// Position of the module among loaded modules, oldest first.
+    /// The module created with the `ExecutionEngine` is at index `0`.
+    pub index: uint,
+    /// Whether code within the module may still be running
+    pub running: bool,
+    /// Whether the module is pinned
+    pub pinned: bool,
+    /// Names of functions defined in the module
+    pub functions: Vec<String>,
+    /// Names of globals defined in the module
+    pub globals: Vec<String>,
+}
+
+/// Reason that `with_analysis` failed to produce a result
+#[deriving(Clone, PartialEq, Eq, Show)]
+pub enum AnalysisError {
+    /// Input failed to compile; diagnostics have been emitted
+    CompileError,
+    /// Input was analyzed, but the given closure panicked with a message
+    Panicked(String),
+}
+
+/// Reason that `remove_module` failed to remove a module
+#[deriving(Copy, Clone, PartialEq, Eq, Show)]
+pub enum RemoveError {
+    /// The module is not contained in the `ExecutionEngine`
+    NotFound,
+    /// LLVM failed to remove the module from the execution engine.
+    /// The module remains in the `ExecutionEngine`.
+    RemoveFailed,
 }
 
 /// A value that can be translated into `ExecutionEngine` input
 pub trait IntoInput {
-    fn into_input(self) -> Input;
+    /// Returns the input, or an error message if the value is not valid input.
+    fn into_input(self) -> Result<Input, String>;
 }
 
 impl<'a> IntoInput for &'a str {
-    fn into_input(self) -> Input {
-        Input::Str(self.to_string())
+    fn into_input(self) -> Result<Input, String> {
+        Ok(Input::Str(self.to_string()))
     }
 }
 
 impl IntoInput for String {
-    fn into_input(self) -> Input {
-        Input::Str(self)
+    fn into_input(self) -> Result<Input, String> {
+        Ok(Input::Str(self))
+    }
+}
+
+impl<'a> IntoInput for &'a [u8] {
+    fn into_input(self) -> Result<Input, String> {
+        match str::from_utf8(self) {
+            Some(s) => Ok(Input::Str(s.to_string())),
+            None => Err(invalid_utf8()),
+        }
+    }
+}
+
+impl IntoInput for Vec<u8> {
+    fn into_input(self) -> Result<Input, String> {
+        match String::from_utf8(self) {
+            Ok(s) => Ok(Input::Str(s)),
+            Err(_) => Err(invalid_utf8()),
+        }
     }
 }
 
 impl IntoInput for Path {
-    fn into_input(self) -> Input {
-        Input::File(self)
+    fn into_input(self) -> Result<Input, String> {
+        Ok(Input::File(self))
     }
 }
 
-type Deps = Vec<Path>;
+/// Returns the error message for source code which is not valid UTF-8.
+fn invalid_utf8() -> String {
+    "input is not valid UTF-8".to_string()
+}
+
+/// Crate names and paths to dynamic library files of crates used by a program
+type Deps = Vec<(String, Path)>;
 
 impl ExecutionEngine {
     /// Constructs a new `ExecutionEngine` with the given library search paths.
+    ///
+    /// # Panics
+    ///
+    /// If the sysroot cannot be found by `get_sysroot`.
     pub fn new(libs: Vec<String>) -> ExecutionEngine {
-        ExecutionEngine::new_with_input(String::new(), libs)
+        match get_sysroot() {
+            Ok(sysroot) => ExecutionEngine::new_with_sysroot(libs, sysroot),
+            Err(e) => panic!("{}", e),
+        }
+    }
+
+    /// Constructs a new `ExecutionEngine` with the given library search paths
+    /// and sysroot. This avoids searching for the sysroot when a caller
+    /// has already found it with `get_sysroot`.
+    pub fn new_with_sysroot(libs: Vec<String>, sysroot: Path) -> ExecutionEngine {
+        match ExecutionEngine::init(String::new().into_input(), libs, sysroot) {
+            Ok(ee) => ee,
+            Err(e) => panic!("ExecutionEngine init input failed to compile:\n{}", e),
+        }
+    }
+
+    /// Constructs a new `ExecutionEngine` with the given library search paths
+    /// which analyzes code for the given target triple.
+    ///
+    /// If the target differs from the host, `add_module` will refuse to
+    /// compile code, as it cannot be run; however, `with_analysis` may still
+    /// be used.
+    pub fn new_with_target(libs: Vec<String>, target: String) -> ExecutionEngine {
+        let mut ee = ExecutionEngine::new(libs);
+
+        if target != config::host_triple() {
+            ee.target = Some(target);
+        }
+
+        ee
     }
 
     /// Constructs a new `ExecutionEngine` with the given starting input
     /// and library search paths.
+    ///
+    /// # Panics
+    ///
+    /// If the input fails to compile.
     pub fn new_with_input<T>(input: T, libs: Vec<String>) -> ExecutionEngine
             where T: IntoInput {
-        let sysroot = get_sysroot();
+        match ExecutionEngine::try_new_with_input(input, libs) {
+            Ok(ee) => ee,
+            Err(e) => panic!("ExecutionEngine init input failed to compile:\n{}", e),
+        }
+    }
 
-        let (llmod, deps) = compile_input(input.into_input(),
-            sysroot.clone(), libs.clone())
-            .expect("ExecutionEngine init input failed to compile");
+    /// Constructs a new `ExecutionEngine` with the given starting input
+    /// and library search paths.
+    /// If the input fails to compile, compiler diagnostics are returned.
+    pub fn try_new_with_input<T>(input: T, libs: Vec<String>)
+            -> Result<ExecutionEngine, String> where T: IntoInput {
+        let sysroot = try!(get_sysroot());
+        ExecutionEngine::init(input.into_input(), libs, sysroot)
+    }
+
+    /// Constructs a new `ExecutionEngine` with the given starting input,
+    /// library search paths, and sysroot.
+    /// If the input fails to compile, compiler diagnostics are returned.
+    pub fn try_new_with_input_sysroot<T>(input: T, libs: Vec<String>, sysroot: Path)
+            -> Result<ExecutionEngine, String> where T: IntoInput {
+        ExecutionEngine::init(input.into_input(), libs, sysroot)
+    }
+
+    fn init(input: Result<Input, String>, libs: Vec<String>, sysroot: Path)
+            -> Result<ExecutionEngine, String> {
+        let (emitter, mut reader) = capture_emitter();
+
+        let res = compile_input(input,
+            sysroot.clone(), libs.clone(), config::No, Vec::new(), None, Some(emitter));
+
+        let (llmod, deps) = match res {
+            Some(r) => r,
+            None => return Err(reader.read_to_string().unwrap_or(String::new())),
+        };
 
         let morestack = morestack_addr();
 
@@ -105,14 +288,20 @@ impl ExecutionEngine {
 
         let ee = ExecutionEngine{
             ee: ee,
-            modules: vec![llmod],
+            modules: vec![Module::new(llmod)],
             lib_paths: libs,
             sysroot: sysroot,
+            opt_level: config::No,
+            cfgs: Vec::new(),
+            module_limit: None,
+            target: None,
+            trace: false,
+            traces: RefCell::new(Vec::new()),
         };
 
         ee.load_deps(&deps);
 
-        ee
+        Ok(ee)
     }
 
     /// Compile a module and add it to the execution engine.
@@ -120,52 +309,378 @@ impl ExecutionEngine {
     /// and `None` will be returned. Otherwise, the module is returned.
     pub fn add_module<T>(&mut self, input: T) -> Option<llvm::ModuleRef>
             where T: IntoInput {
+        self.add_module_with(input, Diagnostics::Print)
+    }
+
+    /// Compile a module and add it to the execution engine.
+    /// Compiler diagnostics are printed to `stderr`, as by `add_module`.
+    /// The codes of all diagnostics emitted, e.g. `"E0252"`, are returned
+    /// along with the module, if it compiled.
+    pub fn add_module_codes<T>(&mut self, input: T)
+            -> (Option<llvm::ModuleRef>, Vec<String>) where T: IntoInput {
+        let (tx, rx) = channel();
+
+        let res = self.add_module_with(input, Diagnostics::Codes(tx, true));
+
+        (res, rx.iter().collect())
+    }
+
+    /// Compile a module and add it to the execution engine.
+    /// Compiler diagnostics are discarded rather than printed to `stderr`;
+    /// the codes of all diagnostics are returned along with the module,
+    /// if it compiled.
+    pub fn add_module_quiet<T>(&mut self, input: T)
+            -> (Option<llvm::ModuleRef>, Vec<String>) where T: IntoInput {
+        let (tx, rx) = channel();
+
+        let res = self.add_module_with(input, Diagnostics::Codes(tx, false));
+
+        (res, rx.iter().collect())
+    }
+
+    /// Compile a module and add it to the execution engine.
+    /// Rather than being printed to `stderr`, any compiler diagnostics are
+    /// collected and returned along with the module, if it compiled.
+    pub fn add_module_capture<T>(&mut self, input: T)
+            -> (Option<llvm::ModuleRef>, String) where T: IntoInput {
+        let (tx, rx) = channel();
+
+        let res = self.add_module_with(input, Diagnostics::Capture(ChanWriter::new(tx)));
+        let msgs = ChanReader::new(rx).read_to_string().unwrap_or(String::new());
+
+        (res, msgs)
+    }
+
+    fn add_module_with<T>(&mut self, input: T, diag: Diagnostics)
+            -> Option<llvm::ModuleRef> where T: IntoInput {
         debug!("compiling module");
 
+        if let Some(ref target) = self.target {
+            emit_error(diag.emitter(),
+                format!("cannot run code compiled for target `{}`", target).as_slice());
+            return None;
+        }
+
         let (llmod, deps) = match compile_input(input.into_input(),
-                self.sysroot.clone(), self.lib_paths.clone()) {
+                self.sysroot.clone(), self.lib_paths.clone(), self.opt_level,
+                self.cfgs.clone(), self.tracer(), diag.emitter()) {
             Some(r) => r,
             None => return None,
         };
 
         self.load_deps(&deps);
 
-        self.modules.push(llmod);
+        // The JIT aborts the process if it cannot resolve a symbol
+        let unresolved = self.unresolved_symbols(llmod);
+
+        if !unresolved.is_empty() {
+            for name in unresolved.iter() {
+                emit_error(diag.emitter(),
+                    format!("unresolved external symbol `{}`", name).as_slice());
+            }
+            unsafe { llvm::LLVMDisposeModule(llmod) };
+            return None;
+        }
+
+        self.modules.push(Module::new(llmod));
 
         unsafe { llvm::LLVMExecutionEngineAddModule(self.ee, llmod); }
 
+        self.enforce_module_limit();
+
         Some(llmod)
     }
 
-    /// Remove the given module from the execution engine.
-    /// The module is destroyed after it is removed.
+    /// Sets the maximum number of modules to keep. When a module is added
+    /// beyond the limit, the oldest modules are removed, if they are finished
+    /// and not pinned.
+    /// The module created with the `ExecutionEngine` is never removed.
+    ///
+    /// The default is `None`, meaning modules are only removed by
+    /// `remove_module`.
+    pub fn set_module_limit(&mut self, limit: Option<uint>) {
+        self.module_limit = limit;
+        self.enforce_module_limit();
+    }
+
+    /// Removes the oldest finished modules until the number of modules
+    /// is within the module limit.
+    fn enforce_module_limit(&mut self) {
+        let limit = match self.module_limit {
+            Some(limit) => limit,
+            None => return,
+        };
+
+        let mut i = 1;
+
+        while self.modules.len() > limit && i < self.modules.len() {
+            if self.modules[i].running {
+                debug!("module limit exceeded; keeping module {} which may be running", i);
+                i += 1;
+            } else if self.modules[i].pinned {
+                debug!("module limit exceeded; keeping pinned module {}", i);
+                i += 1;
+            } else {
+                let llmod = self.modules[i].llmod;
+
+                if let Err(e) = self.remove_module(llmod) {
+                    debug!("module limit exceeded; failed to remove module {}: {}", i, e);
+                    i += 1;
+                }
+            }
+        }
+    }
+
+    /// Removes all finished modules which are not pinned, other than the
+    /// module created with the `ExecutionEngine`.
+    /// Returns the number of modules removed.
+    pub fn remove_finished_modules(&mut self) -> uint {
+        let finished = self.modules.iter().skip(1)
+            .filter(|m| !m.running && !m.pinned).map(|m| m.llmod).collect::<Vec<_>>();
+        let mut n = 0;
+
+        for llmod in finished.into_iter() {
+            match self.remove_module(llmod) {
+                Ok(()) => n += 1,
+                Err(e) => debug!("failed to remove finished module: {}", e),
+            }
+        }
+
+        n
+    }
+
+    /// Marks the given module as finished; i.e. no code within the module
+    /// is running and none will be run unless called by the host.
+    /// Only then is it safe to remove the module.
     ///
     /// # Panics
     ///
     /// If the Module does not exist within this `ExecutionEngine`.
-    pub fn remove_module(&mut self, llmod: llvm::ModuleRef) {
-        match self.modules.iter().position(|p| *p == llmod) {
-            Some(i) => {
-                self.modules.remove(i);
-                let res = unsafe {
-                    llvm::LLVMExecutionEngineRemoveModule(self.ee, llmod)
-                };
+    pub fn set_finished(&mut self, llmod: llvm::ModuleRef) {
+        match self.modules.iter_mut().find(|m| m.llmod == llmod) {
+            Some(m) => m.running = false,
+            None => panic!("Module not contained in ExecutionEngine"),
+        }
+    }
+
+    /// Returns whether code within the given module may still be running.
+    ///
+    /// # Panics
+    ///
+    /// If the Module does not exist within this `ExecutionEngine`.
+    pub fn is_running(&self, llmod: llvm::ModuleRef) -> bool {
+        match self.modules.iter().find(|m| m.llmod == llmod) {
+            Some(m) => m.running,
+            None => panic!("Module not contained in ExecutionEngine"),
+        }
+    }
 
-                assert_eq!(res, 1);
+    /// Sets whether the given module is pinned. A pinned module is not removed
+    /// by `remove_finished_modules` or to satisfy the module limit,
+    /// so functions and globals it defines remain available.
+    ///
+    /// # Panics
+    ///
+    /// If the Module does not exist within this `ExecutionEngine`.
+    pub fn set_pinned(&mut self, llmod: llvm::ModuleRef, pinned: bool) {
+        match self.modules.iter_mut().find(|m| m.llmod == llmod) {
+            Some(m) => m.pinned = pinned,
+            None => panic!("Module not contained in ExecutionEngine"),
+        }
+    }
 
-                unsafe { llvm::LLVMDisposeModule(llmod) };
-            },
+    /// Returns whether the given module is pinned.
+    ///
+    /// # Panics
+    ///
+    /// If the Module does not exist within this `ExecutionEngine`.
+    pub fn is_pinned(&self, llmod: llvm::ModuleRef) -> bool {
+        match self.modules.iter().find(|m| m.llmod == llmod) {
+            Some(m) => m.pinned,
             None => panic!("Module not contained in ExecutionEngine"),
         }
     }
 
+    /// Remove the given module from the execution engine.
+    /// The module is destroyed after it is removed.
+    ///
+    /// Returns `Err(NotFound)` if the module does not exist within this
+    /// `ExecutionEngine` or `Err(RemoveFailed)` if LLVM could not remove it.
+    /// In either case, the module is not destroyed.
+    pub fn remove_module(&mut self, llmod: llvm::ModuleRef) -> Result<(), RemoveError> {
+        let i = match self.modules.iter().position(|m| m.llmod == llmod) {
+            Some(i) => i,
+            None => return Err(RemoveError::NotFound),
+        };
+
+        let res = unsafe {
+            llvm::LLVMExecutionEngineRemoveModule(self.ee, llmod)
+        };
+
+        if res != 1 {
+            return Err(RemoveError::RemoveFailed);
+        }
+
+        self.modules.remove(i);
+
+        unsafe { llvm::LLVMDisposeModule(llmod) };
+
+        Ok(())
+    }
+
     /// Compiles the given input only up to the analysis phase, calling the
     /// given closure with a borrowed reference to the analysis result.
-    pub fn with_analysis<F, R, T>(&self, input: T, f: F) -> Option<R>
+    pub fn with_analysis<F, R, T>(&self, input: T, f: F) -> Result<R, AnalysisError>
             where F: Send, R: Send, T: IntoInput,
             F: for<'tcx> FnOnce(&ty::CrateAnalysis<'tcx>) -> R {
         with_analysis(f, input.into_input(),
-            self.sysroot.clone(), self.lib_paths.clone())
+            self.sysroot.clone(), self.lib_paths.clone(), self.opt_level,
+            self.cfgs.clone(), self.target.clone(), self.tracer(), None)
+    }
+
+    /// Like `with_analysis`, but rather than being printed to `stderr`,
+    /// any compiler diagnostics are collected and returned with the result.
+    pub fn with_analysis_capture<F, R, T>(&self, input: T, f: F)
+            -> (Result<R, AnalysisError>, String)
+            where F: Send, R: Send, T: IntoInput,
+            F: for<'tcx> FnOnce(&ty::CrateAnalysis<'tcx>) -> R {
+        let (emitter, mut reader) = capture_emitter();
+
+        let res = with_analysis(f, input.into_input(),
+            self.sysroot.clone(), self.lib_paths.clone(), self.opt_level,
+            self.cfgs.clone(), self.target.clone(), self.tracer(), Some(emitter));
+        let msgs = reader.read_to_string().unwrap_or(String::new());
+
+        (res, msgs)
+    }
+
+    /// Like `with_analysis`, but any compiler diagnostics are discarded
+    /// rather than printed to `stderr`.
+    pub fn with_analysis_quiet<F, R, T>(&self, input: T, f: F)
+            -> Result<R, AnalysisError>
+            where F: Send, R: Send, T: IntoInput,
+            F: for<'tcx> FnOnce(&ty::CrateAnalysis<'tcx>) -> R {
+        with_analysis(f, input.into_input(),
+            self.sysroot.clone(), self.lib_paths.clone(), self.opt_level,
+            self.cfgs.clone(), self.target.clone(), self.tracer(), Some(box NullEmitter))
+    }
+
+    /// Adds a directory to the library search path for code compiled
+    /// by later calls to `add_module`.
+    pub fn add_search_path(&mut self, path: String) {
+        self.lib_paths.push(path);
+    }
+
+    /// Returns the additional library search paths.
+    pub fn lib_paths(&self) -> &[String] {
+        self.lib_paths.as_slice()
+    }
+
+    /// Returns the sysroot used to find Rust libraries.
+    pub fn sysroot(&self) -> &Path {
+        &self.sysroot
+    }
+
+    /// Returns the target triple of analyzed code, if it differs from the host.
+    pub fn target(&self) -> Option<&str> {
+        self.target.as_ref().map(|s| s.as_slice())
+    }
+
+    /// Returns the optimization level of compiled code.
+    pub fn opt_level(&self) -> OptLevel {
+        self.opt_level
+    }
+
+    /// Returns the version string reported by the `rustc` executable
+    /// in the sysroot, e.g. `"rustc 0.13.0-nightly (...)"`.
+    ///
+    /// Returns `None` if `rustc` could not be run.
+    pub fn rustc_version(&self) -> Option<String> {
+        let rustc = self.sysroot.join("bin").join(rustc_name());
+
+        match Command::new(rustc).arg("--version").output() {
+            Ok(ref out) if out.status.success() =>
+                String::from_utf8(out.output.clone()).ok()
+                    .map(|s| s.as_slice().trim().to_string()),
+            _ => None
+        }
+    }
+
+    /// Sets the optimization level of code compiled by later calls to
+    /// `add_module`. The default is `OptLevel::No`, which favors fast builds.
+    pub fn set_opt_level(&mut self, opt_level: OptLevel) {
+        self.opt_level = opt_level;
+    }
+
+    /// Returns the additional configuration flags of compiled code.
+    pub fn cfgs(&self) -> &[String] {
+        self.cfgs.as_slice()
+    }
+
+    /// Returns whether time spent in each phase of compilation is recorded.
+    pub fn trace(&self) -> bool {
+        self.trace
+    }
+
+    /// Sets whether to record the time spent in each phase of compilation
+    /// by later calls to `add_module` and `with_analysis`.
+    /// Recorded traces are returned by `take_traces`.
+    pub fn set_trace(&mut self, trace: bool) {
+        self.trace = trace;
+    }
+
+    /// Returns traces recorded since the last call, in the order in which
+    /// compilation took place.
+    pub fn take_traces(&self) -> Vec<Trace> {
+        replace(&mut *self.traces.borrow_mut(), Vec::new())
+    }
+
+    /// Returns the collection to which traces are added, if tracing is enabled.
+    fn tracer(&self) -> Option<&RefCell<Vec<Trace>>> {
+        if self.trace { Some(&self.traces) } else { None }
+    }
+
+    /// Sets additional configuration flags, such as `foo` or `feature="foo"`,
+    /// for code compiled by later calls to `add_module` and `with_analysis`.
+    /// Each flag is interpreted as an argument to `rustc --cfg`.
+    pub fn set_cfgs(&mut self, cfgs: Vec<String>) {
+        self.cfgs = cfgs;
+    }
+
+    /// Returns the number of loaded modules, including the module created
+    /// with the `ExecutionEngine`.
+    pub fn module_count(&self) -> uint {
+        self.modules.len()
+    }
+
+    /// Returns a description of the module at the given index, oldest first,
+    /// or `None` if there is no such module.
+    pub fn module_info(&self, index: uint) -> Option<ModuleInfo> {
+        self.modules.get(index).map(|m| {
+            let mut functions = Vec::new();
+            let mut globals = Vec::new();
+
+            for (name, is_fn) in module_symbols(m.llmod, false).into_iter() {
+                if is_fn {
+                    functions.push(name);
+                } else {
+                    globals.push(name);
+                }
+            }
+
+            ModuleInfo{
+                index: index,
+                running: m.running,
+                pinned: m.pinned,
+                functions: functions,
+                globals: globals,
+            }
+        })
+    }
+
+    /// Returns descriptions of all loaded modules, oldest first.
+    pub fn modules(&self) -> Vec<ModuleInfo> {
+        range(0, self.modules.len()).filter_map(|i| self.module_info(i)).collect()
     }
 
     /// Searches for the named function in the set of loaded modules,
@@ -173,21 +688,28 @@ impl ExecutionEngine {
     /// If the function is found, a raw pointer is returned.
     /// If the function is not found, `None` is returned.
     pub fn get_function(&mut self, name: &str) -> Option<*const ()> {
-        name.with_c_str(|s| {
-            for m in self.modules.iter().rev() {
-                let fv = unsafe { llvm::LLVMGetNamedFunction(*m, s) };
-
-                if !fv.is_null() {
-                    let fp = unsafe { llvm::LLVMGetPointerToGlobal(self.ee, fv) };
+        self.find_symbol(name, true).and_then(|(_, fv)| {
+            let fp = unsafe { llvm::LLVMGetPointerToGlobal(self.ee, fv) };
 
-                    assert!(!fp.is_null());
+            if fp.is_null() { None } else { Some(fp) }
+        })
+    }
 
-                    return Some(fp);
-                }
-            }
+    /// Searches for the named function, as `get_function` does, and returns
+    /// it as a value of type `F`; e.g. `fn(int) -> int`.
+    ///
+    /// # Safety
+    ///
+    /// `F` must be a function pointer type matching the signature of the
+    /// named function.
+    ///
+    /// # Panics
+    ///
+    /// If `F` is not the size of a pointer.
+    pub unsafe fn get_fn<F: Copy>(&mut self, name: &str) -> Option<F> {
+        assert_eq!(size_of::<F>(), size_of::<*const ()>());
 
-            None
-        })
+        self.get_function(name).map(|fp| transmute_copy(&fp))
     }
 
     /// Searches for the named global in the set of loaded modules,
@@ -195,16 +717,51 @@ impl ExecutionEngine {
     /// If the global is found, a raw pointer is returned.
     /// If the global is not found, `None` is returned.
     pub fn get_global(&mut self, name: &str) -> Option<*const ()> {
-        name.with_c_str(|s| {
-            for m in self.modules.iter().rev() {
-                let gv = unsafe { llvm::LLVMGetNamedGlobal(*m, s) };
+        self.find_symbol(name, false).and_then(|(_, gv)| {
+            let gp = unsafe { llvm::LLVMGetPointerToGlobal(self.ee, gv) };
 
-                if !gv.is_null() {
-                    let gp = unsafe { llvm::LLVMGetPointerToGlobal(self.ee, gv) };
+            if gp.is_null() { None } else { Some(gp) }
+        })
+    }
 
-                    assert!(!gp.is_null());
+    /// Searches for the named global, as `get_global` does, and returns
+    /// a copy of its value.
+    ///
+    /// # Safety
+    ///
+    /// `T` must be the type of the named global.
+    pub unsafe fn get_global_value<T: Copy>(&mut self, name: &str) -> Option<T> {
+        self.get_global(name).map(|p| *(p as *const T))
+    }
+
+    /// Returns the index of the module, as given by `module_info`, in which
+    /// `get_function` would find the named function.
+    pub fn function_module(&self, name: &str) -> Option<uint> {
+        self.find_symbol(name, true).map(|(i, _)| i)
+    }
+
+    /// Returns the index of the module, as given by `module_info`, in which
+    /// `get_global` would find the named global.
+    pub fn global_module(&self, name: &str) -> Option<uint> {
+        self.find_symbol(name, false).map(|(i, _)| i)
+    }
 
-                    return Some(gp);
+    /// Searches for the named function, if `is_fn` is `true`, or global
+    /// in the set of loaded modules, beginning with the most recently added
+    /// module. Returns the index of the module and the value found.
+    fn find_symbol(&self, name: &str, is_fn: bool) -> Option<(uint, llvm::ValueRef)> {
+        name.with_c_str(|s| {
+            for (i, m) in self.modules.iter().enumerate().rev() {
+                let v = unsafe {
+                    if is_fn {
+                        llvm::LLVMGetNamedFunction(m.llmod, s)
+                    } else {
+                        llvm::LLVMGetNamedGlobal(m.llmod, s)
+                    }
+                };
+
+                if !v.is_null() {
+                    return Some((i, v));
                 }
             }
 
@@ -212,17 +769,55 @@ impl ExecutionEngine {
         })
     }
 
+    /// Returns the names of functions and globals declared, but not defined,
+    /// in the given module which are neither defined in another module
+    /// nor found in a loaded library.
+    fn unresolved_symbols(&self, llmod: llvm::ModuleRef) -> Vec<String> {
+        let lib = match DynamicLibrary::open(None) {
+            Ok(lib) => lib,
+            Err(e) => {
+                debug!("failed to open process symbols: {}", e);
+                return Vec::new();
+            }
+        };
+
+        let mut unresolved = Vec::new();
+
+        for (name, is_fn) in module_symbols(llmod, true).into_iter() {
+            // Intrinsics and `__morestack` are resolved by the JIT itself
+            if name.starts_with("llvm.") || name == "__morestack" {
+                continue;
+            }
+
+            let defined = name.with_c_str(|s| self.modules.iter().any(|m| unsafe {
+                let v = if is_fn {
+                    llvm::LLVMGetNamedFunction(m.llmod, s)
+                } else {
+                    llvm::LLVMGetNamedGlobal(m.llmod, s)
+                };
+
+                !v.is_null() && llvm::LLVMIsDeclaration(v) == 0
+            }));
+
+            if !defined && unsafe { lib.symbol::<u8>(name.as_slice()) }.is_err() {
+                unresolved.push(name);
+            }
+        }
+
+        unresolved
+    }
+
     /// Loads all dependencies of compiled code.
-    /// Expects a series of paths to dynamic library files.
+    /// Expects a series of crate names and paths to dynamic library files.
     fn load_deps(&self, deps: &Deps) {
-        for path in deps.iter() {
-            debug!("loading crate {}", path.display());
+        for &(ref name, ref path) in deps.iter() {
+            debug!("loading crate `{}` from {}", name, path.display());
             path.with_c_str(|s| {
                 let res = unsafe { llvm::LLVMRustLoadDynamicLibrary(s) };
 
                 if res == 0 {
-                    panic!("Failed to load crate {}: {}",
-                        s, llvm_error());
+                    panic!("Failed to load crate `{}` from {}: {}",
+                        name, path.display(), llvm_error());
                 }
             });
         }
@@ -245,15 +840,29 @@ fn llvm_error() -> CString {
 /// `rustc` uses its own executable path to derive the sysroot.
 /// Because we're not `rustc`, we have to go looking for the sysroot.
 ///
-/// To do this, we search the directories in the `PATH` environment variable
+/// If the `RUSTI_SYSROOT` environment variable is set, its value is used.
+/// Otherwise, we search the directories in the `PATH` environment variable
 /// for a file named `rustc` (`rustc.exe` on Windows). Upon finding it,
 /// we use the parent directory of that directory as the sysroot.
 ///
 /// e.g. if `/usr/local/bin` is in `PATH` and `/usr/local/bin/rustc` is found,
 /// `/usr/local` will be the sysroot.
-fn get_sysroot() -> Path {
+///
+/// Returns an error if no sysroot is found or if `RUSTI_SYSROOT` names
+/// an invalid sysroot.
+pub fn get_sysroot() -> Result<Path, String> {
+    if let Some(sysroot) = getenv("RUSTI_SYSROOT") {
+        let sysroot = Path::new(sysroot);
+
+        debug!("sysroot from RUSTI_SYSROOT {}", sysroot.display());
+
+        try!(check_sysroot(&sysroot));
+
+        return Ok(sysroot);
+    }
+
     if let Some(path) = getenv_as_bytes("PATH") {
-        let rustc = if cfg!(windows) { "rustc.exe" } else { "rustc" };
+        let rustc = rustc_name();
 
         debug!("searching for sysroot in PATH {}",
             String::from_utf8_lossy(path.as_slice()));
@@ -262,15 +871,192 @@ fn get_sysroot() -> Path {
             if p.join(rustc).is_file() {
                 debug!("sysroot from PATH entry {}", p.display());
                 p.pop();
-                return p;
+                return Ok(p);
             }
         }
     }
 
-    panic!("Could not find sysroot");
+    Err("could not find sysroot; set RUSTI_SYSROOT or add rustc to PATH".to_string())
+}
+
+/// Checks that the given sysroot contains Rust libraries.
+pub fn check_sysroot(sysroot: &Path) -> Result<(), String> {
+    if sysroot.join("lib").join("rustlib").is_dir() {
+        Ok(())
+    } else {
+        Err(format!("invalid sysroot {}: lib/rustlib not found", sysroot.display()))
+    }
+}
+
+/// Returns the names of functions and globals in the given module which are
+/// declared, but not defined, if `declared` is `true`; otherwise, those which
+/// are defined. Each name is paired with `true` if it is a function.
+fn module_symbols(llmod: llvm::ModuleRef, declared: bool) -> Vec<(String, bool)> {
+    let mut names = Vec::new();
+
+    unsafe {
+        let mut f = llvm::LLVMGetFirstFunction(llmod);
+
+        while !f.is_null() {
+            if (llvm::LLVMIsDeclaration(f) != 0) == declared {
+                names.push((value_name(f), true));
+            }
+            f = llvm::LLVMGetNextFunction(f);
+        }
+
+        let mut g = llvm::LLVMGetFirstGlobal(llmod);
+
+        while !g.is_null() {
+            if (llvm::LLVMIsDeclaration(g) != 0) == declared {
+                names.push((value_name(g), false));
+            }
+            g = llvm::LLVMGetNextGlobal(g);
+        }
+    }
+
+    names
+}
+
+/// Returns the name of an LLVM value.
+unsafe fn value_name(v: llvm::ValueRef) -> String {
+    CString::new(llvm::LLVMGetValueName(v), false).as_str().unwrap_or("").to_string()
+}
+
+/// Emits an error message through the given emitter or, if it is `None`,
+/// to `stderr`.
+fn emit_error(emitter: Option<Box<Emitter + Send>>, msg: &str) {
+    let mut emitter = emitter.unwrap_or_else(|| box EmitterWriter::stderr(
+        diagnostic::Auto, None) as Box<Emitter + Send>);
+
+    emitter.emit(None, msg, None, Level::Error);
+}
+
+/// Returns the file name of the `rustc` executable.
+fn rustc_name() -> &'static str {
+    if cfg!(windows) { "rustc.exe" } else { "rustc" }
+}
+
+/// Discards all diagnostic messages
+struct NullEmitter;
+
+impl Emitter for NullEmitter {
+    fn emit(&mut self, _cmsp: Option<(&CodeMap, Span)>, _msg: &str,
+        _code: Option<&str>, _lvl: Level) {}
+
+    fn custom_emit(&mut self, _cm: &CodeMap, _sp: RenderSpan,
+        _msg: &str, _lvl: Level) {}
+}
+
+/// Sends the code of each diagnostic which has one and, if constructed
+/// to do so, prints diagnostics to `stderr`
+struct CodeEmitter {
+    emitter: Option<EmitterWriter>,
+    codes: Sender<String>,
+}
+
+impl CodeEmitter {
+    fn new(codes: Sender<String>, print: bool) -> CodeEmitter {
+        CodeEmitter{
+            emitter: if print {
+                Some(EmitterWriter::stderr(diagnostic::Auto,
+                    Some(Registry::new(&rustc::DIAGNOSTICS))))
+            } else {
+                None
+            },
+            codes: codes,
+        }
+    }
+}
+
+impl Emitter for CodeEmitter {
+    fn emit(&mut self, cmsp: Option<(&CodeMap, Span)>, msg: &str,
+            code: Option<&str>, lvl: Level) {
+        if let Some(code) = code {
+            let _ = self.codes.send_opt(code.to_string());
+        }
+        if let Some(ref mut emitter) = self.emitter {
+            emitter.emit(cmsp, msg, code, lvl);
+        }
+    }
+
+    fn custom_emit(&mut self, cm: &CodeMap, sp: RenderSpan,
+            msg: &str, lvl: Level) {
+        if let Some(ref mut emitter) = self.emitter {
+            emitter.custom_emit(cm, sp, msg, lvl);
+        }
+    }
+}
+
+/// Destination of diagnostics from `add_module_with`. Unlike an emitter,
+/// it may be used again after compilation to report errors found later.
+#[deriving(Clone)]
+enum Diagnostics {
+    /// Diagnostics are printed to `stderr`
+    Print,
+    /// Diagnostic codes are sent and, if `true`, diagnostics are printed
+    Codes(Sender<String>, bool),
+    /// Diagnostics are formatted as by `capture_emitter`
+    Capture(ChanWriter),
+}
+
+impl Diagnostics {
+    /// Returns an emitter for these diagnostics; `None` prints to `stderr`.
+    fn emitter(&self) -> Option<Box<Emitter + Send>> {
+        match *self {
+            Diagnostics::Print => None,
+            Diagnostics::Codes(ref tx, print) =>
+                Some(box CodeEmitter::new(tx.clone(), print) as Box<Emitter + Send>),
+            Diagnostics::Capture(ref w) =>
+                Some(box EmitterWriter::new(box w.clone(),
+                    Some(Registry::new(&rustc::DIAGNOSTICS))) as Box<Emitter + Send>),
+        }
+    }
+}
+
+/// Returns an emitter which formats diagnostics as they would be printed
+/// to `stderr` and a reader from which the formatted messages can be read.
+/// The reader will reach end-of-file once the emitter is dropped.
+fn capture_emitter() -> (Box<Emitter + Send>, ChanReader) {
+    let (tx, rx) = channel();
+
+    let emitter = EmitterWriter::new(box ChanWriter::new(tx),
+        Some(Registry::new(&rustc::DIAGNOSTICS)));
+
+    (box emitter, ChanReader::new(rx))
+}
+
+/// Returns a builder for a task which runs the compiler.
+///
+/// The task's `stderr` is discarded, eliminating the useless
+/// "task '<...>' panicked" message, unless the `RUSTI_DEBUG_COMPILE`
+/// environment variable is set. Then, messages and backtraces
+/// from a panicking compiler are printed.
+fn compile_task() -> Builder {
+    let task = Builder::new();
+
+    if getenv("RUSTI_DEBUG_COMPILE").is_some() {
+        task
+    } else {
+        task.stderr(box NullWriter)
+    }
 }
 
-fn build_exec_options(sysroot: Path, libs: Vec<String>) -> Options {
+/// Builds a compiler session. If `emitter` is `None`, diagnostics are
+/// printed to `stderr`.
+fn build_exec_session(opts: Options, emitter: Option<Box<Emitter + Send>>) -> Session {
+    match emitter {
+        Some(emitter) => {
+            let handler = diagnostic::mk_handler(emitter);
+            let span_handler = diagnostic::mk_span_handler(handler, CodeMap::new());
+
+            build_session_(opts, None, span_handler)
+        }
+        None => build_session(opts, None, Registry::new(&rustc::DIAGNOSTICS)),
+    }
+}
+
+fn build_exec_options(sysroot: Path, libs: Vec<String>, opt_level: OptLevel,
+        cfgs: Vec<String>, target: Option<String>) -> Options {
     let mut opts = basic_options();
 
     // librustc derives sysroot from the executable name.
@@ -281,8 +1067,12 @@ fn build_exec_options(sysroot: Path, libs: Vec<String>) -> Options {
         opts.search_paths.add_path(p.as_slice());
     }
 
-    // Prefer faster build times
-    opts.optimize = config::No;
+    opts.optimize = opt_level;
+    opts.cfg = config::parse_cfgspecs(cfgs);
+
+    if let Some(target) = target {
+        opts.target_triple = target;
+    }
 
     // Don't require a `main` function
     opts.crate_types = vec![config::CrateTypeDylib];
@@ -290,43 +1080,150 @@ fn build_exec_options(sysroot: Path, libs: Vec<String>) -> Options {
     opts
 }
 
+/// Name of a compilation phase and the time spent in it,
+/// or `None` when the phase begins
+type PhaseTime = (&'static str, Option<Duration>);
+
+/// Sends the name of each compilation phase as it begins and the time spent
+/// in it as it ends, if tracing is enabled.
+struct PhaseTrace {
+    tx: Option<Sender<PhaseTime>>,
+    phase: &'static str,
+    start: u64,
+}
+
+impl PhaseTrace {
+    fn new(tx: Option<Sender<PhaseTime>>) -> PhaseTrace {
+        PhaseTrace{
+            tx: tx,
+            phase: "",
+            start: 0,
+        }
+    }
+
+    fn enter(&mut self, phase: &'static str) {
+        if let Some(ref tx) = self.tx {
+            tx.send((phase, None));
+            self.phase = phase;
+            self.start = time::precise_time_ns();
+        }
+    }
+
+    fn exit(&mut self) {
+        if let Some(ref tx) = self.tx {
+            let ns = time::precise_time_ns() - self.start;
+            tx.send((self.phase, Some(Duration::nanoseconds(ns as i64))));
+        }
+    }
+}
+
+/// Creates a channel for `PhaseTrace`, if `trace` is `true`.
+fn trace_channel(trace: bool) -> (Option<Sender<PhaseTime>>, Option<Receiver<PhaseTime>>) {
+    if trace {
+        let (tx, rx) = channel();
+        (Some(tx), Some(rx))
+    } else {
+        (None, None)
+    }
+}
+
+/// Adds a `Trace` of the time spent in each compilation phase, as sent by
+/// `PhaseTrace`, to `traces`. A phase which began, but did not end,
+/// is recorded as unfinished.
+fn add_trace(traces: Option<&RefCell<Vec<Trace>>>, what: &'static str,
+        rx: Option<Receiver<PhaseTime>>) {
+    let (traces, rx) = match (traces, rx) {
+        (Some(traces), Some(rx)) => (traces, rx),
+        _ => return,
+    };
+
+    let mut phases = Vec::new();
+
+    for (phase, time) in rx.iter() {
+        match time {
+            Some(d) => {
+                phases.pop();
+                phases.push((phase, Some(d)));
+            }
+            None => phases.push((phase, None)),
+        }
+    }
+
+    traces.borrow_mut().push(Trace{
+        what: what,
+        phases: phases,
+    });
+}
+
 /// Compiles input up to phase 4, translation to LLVM.
 ///
 /// Returns the LLVM `ModuleRef` and a series of paths to dynamic libraries
 /// for crates used in the given input.
-fn compile_input(input: Input, sysroot: Path, libs: Vec<String>)
+///
+/// If `traces` is given, time spent in each phase is added to it.
+/// If `emitter` is `None`, diagnostics are printed to `stderr`.
+fn compile_input(input: Result<Input, String>, sysroot: Path, libs: Vec<String>,
+        opt_level: OptLevel, cfgs: Vec<String>, traces: Option<&RefCell<Vec<Trace>>>,
+        emitter: Option<Box<Emitter + Send>>)
         -> Option<(llvm::ModuleRef, Deps)> {
-    // Eliminates the useless "task '<...>' panicked" message
-    let task = Builder::new().stderr(box NullWriter);
+    let input = match input {
+        Ok(input) => input,
+        Err(e) => {
+            emit_error(emitter, e.as_slice());
+            return None;
+        }
+    };
+
+    let task = compile_task();
+
+    let (trace_tx, trace_rx) = trace_channel(traces.is_some());
 
     let res = task.spawn(move || {
-        let opts = build_exec_options(sysroot, libs);
-        let sess = build_session(opts, None, Registry::new(&rustc::DIAGNOSTICS));
+        let mut trace = PhaseTrace::new(trace_tx);
+
+        let opts = build_exec_options(sysroot, libs, opt_level, cfgs, None);
+        let sess = build_exec_session(opts, emitter);
 
         let cfg = build_configuration(&sess);
 
         let id = "repl".to_string();
 
+        trace.enter("phase 1 (parse input)");
         let krate = driver::phase_1_parse_input(&sess, cfg, &input);
+        trace.exit();
 
+        trace.enter("phase 2 (configure and expand)");
         let krate = driver::phase_2_configure_and_expand(&sess, krate,
-            id.as_slice(), None).expect("phase_2 returned `None`");
+            id.as_slice(), None);
+        trace.exit();
+
+        // Diagnostics for a failed expansion have already been emitted
+        let krate = match krate {
+            Some(krate) => krate,
+            None => return None,
+        };
 
         let mut forest = ast_map::Forest::new(krate);
         let ast_map = driver::assign_node_ids_and_map(&sess, &mut forest);
 
         let arenas = ty::CtxtArenas::new();
 
+        trace.enter("phase 3 (run analysis passes)");
         let analysis = driver::phase_3_run_analysis_passes(sess, ast_map, &arenas, id);
+        trace.exit();
 
+        trace.enter("phase 4 (translate to LLVM)");
         let (tcx, trans) = driver::phase_4_translate_to_llvm(analysis);
+        trace.exit();
 
         let crates = tcx.sess.cstore.get_used_crates(RequireDynamic);
 
         // Collect crates used in the session.
         // Reverse order finds dependencies first.
         let deps = crates.into_iter().rev()
-            .filter_map(|(_, p)| p).collect();
+            .filter_map(|(cnum, p)| p.map(|p|
+                (tcx.sess.cstore.get_crate_data(cnum).name.clone(), p)))
+            .collect();
 
         assert_eq!(trans.modules.len(), 1);
         let llmod = trans.modules[0].llmod;
@@ -334,45 +1231,96 @@ fn compile_input(input: Input, sysroot: Path, libs: Vec<String>)
         // Workaround because raw pointers do not impl Send
         let modp: uint = unsafe { transmute(llmod) };
 
-        (modp, deps)
+        Some((modp, deps))
     }).join();
 
+    add_trace(traces, "compile", trace_rx);
+
     match res {
-        Ok((llmod, deps)) => Some((unsafe { transmute(llmod) }, deps)),
-        Err(_) => None,
+        Ok(Some((llmod, deps))) => Some((unsafe { transmute(llmod) }, deps)),
+        Ok(None) | Err(_) => None,
     }
 }
 
 /// Compiles input up to phase 3, type/region check analysis, and calls
 /// the given closure with the resulting `CrateAnalysis`.
-fn with_analysis<F, R>(f: F, input: Input, sysroot: Path, libs: Vec<String>) -> Option<R>
+///
+/// If `traces` is given, time spent in each phase is added to it.
+/// If `emitter` is `None`, diagnostics are printed to `stderr`.
+fn with_analysis<F, R>(f: F, input: Result<Input, String>, sysroot: Path,
+        libs: Vec<String>, opt_level: OptLevel, cfgs: Vec<String>,
+        target: Option<String>, traces: Option<&RefCell<Vec<Trace>>>,
+        emitter: Option<Box<Emitter + Send>>)
+        -> Result<R, AnalysisError>
         where F: Send, R: Send,
         F: for<'tcx> FnOnce(&ty::CrateAnalysis<'tcx>) -> R {
-    // Eliminates the useless "task '<...>' panicked" message
-    let task = Builder::new().stderr(box NullWriter);
+    let input = match input {
+        Ok(input) => input,
+        Err(e) => {
+            emit_error(emitter, e.as_slice());
+            return Err(AnalysisError::CompileError);
+        }
+    };
+
+    let task = compile_task();
+
+    // Signals that compilation succeeded and the closure was called
+    let (tx, rx) = channel();
+
+    let (trace_tx, trace_rx) = trace_channel(traces.is_some());
 
     let res = task.spawn(move || {
-        let opts = build_exec_options(sysroot, libs);
-        let sess = build_session(opts, None, Registry::new(&rustc::DIAGNOSTICS));
+        let mut trace = PhaseTrace::new(trace_tx);
+
+        let opts = build_exec_options(sysroot, libs, opt_level, cfgs, target);
+        let sess = build_exec_session(opts, emitter);
 
         let cfg = build_configuration(&sess);
 
         let id = "repl".to_string();
 
+        trace.enter("phase 1 (parse input)");
         let krate = driver::phase_1_parse_input(&sess, cfg, &input);
+        trace.exit();
 
+        trace.enter("phase 2 (configure and expand)");
         let krate = driver::phase_2_configure_and_expand(&sess, krate,
-            id.as_slice(), None).expect("phase_2 returned `None`");
+            id.as_slice(), None);
+        trace.exit();
+
+        // Diagnostics for a failed expansion have already been emitted
+        let krate = match krate {
+            Some(krate) => krate,
+            None => return None,
+        };
 
         let mut forest = ast_map::Forest::new(krate);
         let ast_map = driver::assign_node_ids_and_map(&sess, &mut forest);
 
         let arenas = ty::CtxtArenas::new();
 
+        trace.enter("phase 3 (run analysis passes)");
         let analysis = driver::phase_3_run_analysis_passes(sess, ast_map, &arenas, id);
+        trace.exit();
+
+        tx.send(());
 
-        f(&analysis)
+        Some(f(&analysis))
     }).join();
 
-    res.ok()
+    add_trace(traces, "analysis", trace_rx);
+
+    match res {
+        Ok(Some(r)) => Ok(r),
+        Ok(None) => Err(AnalysisError::CompileError),
+        Err(_) if rx.try_recv().is_err() => Err(AnalysisError::CompileError),
+        Err(e) => Err(AnalysisError::Panicked(
+            if let Some(s) = e.downcast_ref::<&'static str>() {
+                s.to_string()
+            } else if let Some(s) = e.downcast_ref::<String>() {
+                s.clone()
+            } else {
+                "Box<Any>".to_string()
+            })),
+    }
 }
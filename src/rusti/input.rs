@@ -9,19 +9,32 @@
 //! Parsing REPL input statements, including Rust code and `rusti` commands.
 
 use std::borrow::Cow::*;
-use std::io::{BufferedReader, EndOfFile, File, IoResult, stderr};
+use std::io::{Append, BufferedReader, EndOfFile, File, IoResult, Write, stderr, stdin};
+use std::io::stdio::stdin_raw;
 use std::io::util::NullWriter;
 use std::mem::swap;
+use std::os;
 use std::str::CowString;
 use std::thread::Builder;
 
 use super::rustc;
 
+use super::syntax::ast;
 use super::syntax::ast::Decl_::*;
+use super::syntax::ast::Expr_::*;
+use super::syntax::ast::Item_::*;
+use super::syntax::ast::Mac_::MacInvocTT;
 use super::syntax::ast::MacStmtStyle::*;
+use super::syntax::ast::Pat_::PatIdent;
 use super::syntax::ast::Stmt_::*;
+use super::syntax::ast::Ty_::{TyInfer, TyPath, TyTup};
+use super::syntax::abi;
+use super::syntax::ast_util::walk_pat;
+use super::syntax::attr;
 use super::syntax::ast::ViewItem_::*;
-use super::syntax::codemap::{BytePos, CodeMap, Span};
+use super::syntax::ast::ViewPath_::*;
+use super::syntax::ast::PathListItem_::*;
+use super::syntax::codemap::{BytePos, CodeMap, FileMap, Span};
 use super::syntax::diagnostic::{Auto, Emitter, EmitterWriter};
 use super::syntax::diagnostic::{Level, RenderSpan, mk_handler};
 use super::syntax::diagnostic::Level::*;
@@ -29,23 +42,41 @@ use super::syntax::diagnostics::registry::Registry;
 use super::syntax::parse::classify;
 use super::syntax::parse::{new_parse_sess, string_to_filemap, filemap_to_parser};
 use super::syntax::parse::attr::ParserAttr;
+use super::syntax::parse::lexer::{Reader, StringReader};
 use super::syntax::parse::token::{mod, keywords};
+use super::syntax::print::pprust;
+use super::syntax::visit::{mod, Visitor};
 
 use super::readline;
 
 pub use self::InputResult::*;
 use self::ViewItem::*;
 
-pub struct FileReader {
-    reader: BufferedReader<File>,
+/// Default maximum number of lines kept in input history
+pub const DEFAULT_HISTORY_SIZE: uint = 1000;
+
+/// Reads input from a file or other non-interactive source
+pub struct FileReader<R> {
+    reader: BufferedReader<R>,
     buffer: String,
+    /// Name of the input source, used in parser diagnostics
+    name: Option<String>,
 }
 
-impl FileReader {
-    pub fn new(f: File) -> FileReader {
+impl FileReader<File> {
+    pub fn new(f: File) -> FileReader<File> {
+        let name = f.path().as_str().map(|s| s.to_string());
+        FileReader::with_reader(f, name)
+    }
+}
+
+impl<R: Reader> FileReader<R> {
+    /// Constructs a `FileReader` reading from any `Reader`.
+    pub fn with_reader(r: R, name: Option<String>) -> FileReader<R> {
         FileReader{
-            reader: BufferedReader::new(f),
+            reader: BufferedReader::new(r),
             buffer: String::new(),
+            name: name,
         }
     }
 
@@ -63,9 +94,16 @@ impl FileReader {
                 if buf.is_empty() {
                     truncate_newline(&mut line);
                     return parse_command(line.as_slice());
-                } else {
-                    self.buffer = line;
-                    break;
+                }
+
+                // A line beginning with `.` within an incomplete item,
+                // e.g. a method call, is not a command.
+                match self.parse(buf.as_slice(), true) {
+                    More => buf.push_str(line.as_slice()),
+                    res => {
+                        self.buffer = line;
+                        return res;
+                    }
                 }
             } else {
                 buf.push_str(line.as_slice());
@@ -73,13 +111,16 @@ impl FileReader {
         }
 
         if !buf.is_empty() {
-            parse_program(buf.as_slice(), false,
-                self.reader.get_ref().path().as_str())
+            self.parse(buf.as_slice(), false)
         } else {
             Eof
         }
     }
 
+    fn parse(&self, code: &str, filter: bool) -> InputResult {
+        parse_program(code, filter, self.name.as_ref().map(|s| s.as_slice()))
+    }
+
     fn read_line(&mut self) -> IoResult<String> {
         if self.buffer.is_empty() {
             self.reader.read_line()
@@ -101,32 +142,217 @@ fn truncate_newline(s: &mut String) {
 /// Reads input from `stdin`
 pub struct InputReader {
     buffer: String,
+    /// Whether `stdin` is a terminal. If it is not, lines are read directly
+    /// from `stdin`, without prompts or history.
+    interactive: bool,
+    /// File to which input history is saved
+    history_file: Option<Path>,
+    /// Lines of input history, oldest first
+    history: Vec<String>,
+    /// Maximum number of lines kept in history
+    history_size: uint,
 }
 
 impl InputReader {
     /// Constructs a new `InputReader` reading from `stdin`.
+    ///
+    /// If `stdin` is a terminal, history is loaded from `$HOME/.rusti_history`
+    /// and each line of input is appended to it. At most
+    /// `DEFAULT_HISTORY_SIZE` lines are kept.
     pub fn new() -> InputReader {
-        InputReader{
+        let interactive = stdin_raw().isatty();
+
+        let history_file = if interactive {
+            os::homedir().map(|p| p.join(".rusti_history"))
+        } else {
+            None
+        };
+
+        let mut history = Vec::new();
+
+        if let Some(ref path) = history_file {
+            readline::load_history(path);
+
+            if let Ok(text) = File::open(path).read_to_string() {
+                history.extend(text.as_slice().lines().map(|s| s.to_string()));
+            }
+        }
+
+        let mut reader = InputReader{
             buffer: String::new(),
+            interactive: interactive,
+            history_file: history_file,
+            history: history,
+            history_size: DEFAULT_HISTORY_SIZE,
+        };
+
+        reader.set_history_size(DEFAULT_HISTORY_SIZE);
+        reader
+    }
+
+    /// Sets the maximum number of lines kept in input history. Older lines
+    /// are removed, including from the history file.
+    pub fn set_history_size(&mut self, size: uint) {
+        if !self.interactive {
+            return;
+        }
+
+        self.history_size = size;
+        readline::set_history_size(size);
+
+        if self.history.len() > size {
+            self.write_history();
+        }
+    }
+
+    /// Returns lines of input history, oldest first.
+    pub fn history(&self) -> &[String] {
+        self.history.as_slice()
+    }
+
+    /// Removes all input history, including that saved to the history file.
+    pub fn clear_history(&mut self) {
+        if !self.interactive {
+            return;
+        }
+
+        readline::clear_history();
+        self.history.clear();
+
+        if let Some(ref path) = self.history_file {
+            if let Err(e) = File::create(path) {
+                debug!("failed to truncate history file {}: {}", path.display(), e);
+            }
+        }
+    }
+
+    /// Reads a single line of input. The trailing newline is truncated.
+    /// Returns `None` if end-of-file is reached.
+    fn read_line(&mut self, prompt: &str) -> Option<String> {
+        if self.interactive {
+            readline::read_line(prompt)
+        } else {
+            match stdin().read_line() {
+                Ok(mut line) => {
+                    truncate_newline(&mut line);
+                    Some(line)
+                }
+                Err(_) => None,
+            }
+        }
+    }
+
+    /// Reads a single line of input without line editing.
+    /// The trailing newline is truncated.
+    /// Returns `None` if end-of-file is reached or reading is interrupted.
+    fn read_raw_line(&mut self) -> Option<String> {
+        if self.interactive {
+            readline::read_raw_line()
+        } else {
+            match stdin().read_line() {
+                Ok(mut line) => {
+                    truncate_newline(&mut line);
+                    Some(line)
+                }
+                Err(_) => None,
+            }
+        }
+    }
+
+    /// Sets the prompt displayed when `Ctrl-C` discards the current input.
+    pub fn set_interrupt_prompt(&mut self, prompt: &str) {
+        if self.interactive {
+            readline::set_interrupt_prompt(prompt);
+        }
+    }
+
+    /// Returns whether input was interrupted with `Ctrl-C` while reading
+    /// the last line. Any input preceding the interrupt should be discarded.
+    fn take_interrupt(&mut self) -> bool {
+        self.interactive && readline::take_interrupt()
+    }
+
+    /// Sets the candidates for tab-completion of interactive input.
+    /// Lines beginning with `.` are completed against `commands`;
+    /// other input is completed against `names`.
+    pub fn set_completions(&mut self, commands: Vec<String>, names: Vec<String>) {
+        if self.interactive {
+            readline::set_completions(commands, names);
+        }
+    }
+
+    /// Adds a line to input history.
+    fn push_history(&mut self, line: &str) {
+        if !self.interactive {
+            return;
+        }
+
+        readline::push_history(line);
+        self.history.push(line.to_string());
+
+        // Once history is full, the file is rewritten without its oldest line
+        if self.history.len() > self.history_size {
+            self.write_history();
+        } else if let Some(ref path) = self.history_file {
+            let res = File::open_mode(path, Append, Write)
+                .write_line(line);
+
+            if let Err(e) = res {
+                debug!("failed to write history file {}: {}", path.display(), e);
+            }
+        }
+    }
+
+    /// Removes the oldest lines of history in excess of the history size
+    /// and writes the remaining lines to the history file, replacing its
+    /// contents.
+    fn write_history(&mut self) {
+        if self.history.len() > self.history_size {
+            let excess = self.history.len() - self.history_size;
+            self.history = self.history.slice_from(excess).to_vec();
+        }
+
+        if let Some(ref path) = self.history_file {
+            let history = &self.history;
+            let res = File::create(path).and_then(|mut f| {
+                for line in history.iter() {
+                    try!(f.write_line(line.as_slice()));
+                }
+                Ok(())
+            });
+
+            if let Err(e) = res {
+                debug!("failed to write history file {}: {}", path.display(), e);
+            }
         }
     }
 
     /// Reads a single command, item, or statement from `stdin`.
     /// Returns `More` if further input is required for a complete result.
     /// In this case, the input received so far is buffered internally.
+    /// Buffered input is discarded if reading is interrupted by `Ctrl-C`.
     pub fn read_input(&mut self, prompt: &str) -> InputResult {
-        let line = match readline::read_line(prompt) {
+        let line = match self.read_line(prompt) {
             Some(s) => s,
             None => return Eof,
         };
 
+        if self.take_interrupt() {
+            self.buffer.clear();
+        }
+
+        self.parse_line(line)
+    }
+
+    /// Parses a line of input following any input buffered internally.
+    fn parse_line(&mut self, line: String) -> InputResult {
         self.buffer.push_str(line.as_slice());
 
         if self.buffer.is_empty() {
             return Empty;
         }
 
-        readline::push_history(line.as_slice());
+        self.push_history(line.as_slice());
 
         let res = if self.buffer.starts_with(".") {
             parse_command(self.buffer.as_slice())
@@ -143,8 +369,11 @@ impl InputReader {
         res
     }
 
-    /// Reads a block of input until receiving a line consisting only of `.`,
-    /// which will return input, or `.q`, which will cancel and return `Empty`.
+    /// Reads a block of input until receiving a line consisting only of
+    /// `.` or `;;`, which will return input, or `.q`, which will cancel and
+    /// return `Empty`. End-of-file will also return any input received.
+    /// Such a line within a string literal or block comment is read as
+    /// part of the input.
     ///
     /// # Panics
     ///
@@ -156,24 +385,70 @@ impl InputReader {
         let mut buf = String::new();
 
         loop {
-            let line = match readline::read_line(prompt) {
+            let line = match self.read_line(prompt) {
                 Some(s) => s,
-                None => return Eof,
+                None if buf.is_empty() => return Eof,
+                None => return parse_block(buf.as_slice()),
             };
 
+            // An interrupt discards the block; the line that follows
+            // is read as ordinary input.
+            if self.take_interrupt() {
+                return self.parse_line(line);
+            }
+
             if !line.is_empty() {
-                readline::push_history(line.as_slice());
+                self.push_history(line.as_slice());
             }
 
-            if line == ".q" {
-                return Empty;
-            } else if line == "." {
-                return parse_program(buf.as_slice(), true, None);
+            let is_end = line == ".q" || line == "." || line == ";;";
+
+            if is_end && !ends_within_literal(buf.as_slice()) {
+                if line == ".q" {
+                    return Empty;
+                }
+                return parse_block(buf.as_slice());
+            }
+
+            buf.push_str(line.as_slice());
+            buf.push('\n');
+        }
+    }
+
+    /// Reads raw input, without line editing or history, until receiving
+    /// a line consisting only of `.end`. All input is then parsed as one
+    /// program. End-of-file will also return any input received.
+    ///
+    /// # Panics
+    ///
+    /// If the internal buffer contains any data; i.e. if the last
+    /// result from a call to `read_input` returned `More`.
+    pub fn read_paste_input(&mut self) -> InputResult {
+        assert!(self.buffer.is_empty());
+
+        let mut buf = String::new();
+
+        loop {
+            let line = match self.read_raw_line() {
+                Some(line) => line,
+                // An interrupt discards pasted input
+                None if self.take_interrupt() => {
+                    println!("");
+                    return Empty;
+                }
+                None if buf.is_empty() => return Eof,
+                None => break,
+            };
+
+            if line.as_slice().trim_right() == ".end" {
+                break;
             }
 
             buf.push_str(line.as_slice());
             buf.push('\n');
         }
+
+        parse_program(buf.as_slice(), false, None)
     }
 }
 
@@ -202,6 +477,300 @@ pub enum ViewItem {
     Use,
 }
 
+/// Kind of module-level item
+#[deriving(Copy, PartialEq, Eq, Show)]
+pub enum ItemKind {
+    Const,
+    Enum,
+    ExternBlock,
+    Fn,
+    Impl,
+    Macro,
+    Mod,
+    Static,
+    Struct,
+    Trait,
+    TraitImpl,
+    Type,
+}
+
+/// Module-level item
+#[deriving(Clone, Show)]
+pub struct Item {
+    /// Kind of item
+    pub kind: ItemKind,
+    /// Name defined by the item; for `impl` blocks, including trait impls,
+    /// the name of the implementing type
+    pub name: Option<String>,
+    /// Item source code
+    pub source: String,
+    /// Parameter and return types of a function which may be called
+    /// through an `extern` declaration, if the item is such a function
+    pub fn_types: Option<FnTypes>,
+    /// Signature of a function item
+    pub fn_sig: Option<FnSig>,
+}
+
+/// Signature of a function item, as declared
+#[deriving(Clone, Show)]
+pub struct FnSig {
+    /// Whether the function is `unsafe`
+    pub fn_style: ast::FnStyle,
+    /// ABI with which the function is declared
+    pub abi: abi::Abi,
+    /// Whether the function declares any parameters
+    pub takes_args: bool,
+    /// Whether the function returns a value other than `()`
+    pub returns_value: bool,
+}
+
+impl FnSig {
+    /// Returns the signature of a function item.
+    fn from_ast(item: &ast::Item) -> Option<FnSig> {
+        match item.node {
+            ItemFn(ref decl, fn_style, abi, _, _) => Some(FnSig{
+                fn_style: fn_style,
+                abi: abi,
+                takes_args: !decl.inputs.is_empty(),
+                returns_value: match decl.output {
+                    ast::Return(ref ty) => match ty.node {
+                        TyTup(ref tys) => !tys.is_empty(),
+                        _ => true,
+                    },
+                    ast::NoReturn(_) => false,
+                },
+            }),
+            _ => None
+        }
+    }
+}
+
+/// Parameter and return types of a function, as source code
+#[deriving(Clone, Show)]
+pub struct FnTypes {
+    pub inputs: Vec<String>,
+    pub output: String,
+}
+
+impl FnTypes {
+    /// Returns the types of a function item, if it is neither generic,
+    /// `unsafe`, nor declared with an ABI other than `Rust`, and does not
+    /// set its own symbol name.
+    fn from_ast(item: &ast::Item) -> Option<FnTypes> {
+        match item.node {
+            ItemFn(ref decl, ast::NormalFn, abi::Rust, ref generics, _)
+                    if !generics.is_parameterized() &&
+                        !attr::contains_name(item.attrs.as_slice(), "no_mangle") &&
+                        !attr::contains_name(item.attrs.as_slice(), "export_name") => {
+                let output = match decl.output {
+                    ast::Return(ref ty) => pprust::ty_to_string(&**ty),
+                    ast::NoReturn(_) => "!".to_string(),
+                };
+
+                Some(FnTypes{
+                    inputs: decl.inputs.iter()
+                        .map(|arg| pprust::ty_to_string(&*arg.ty)).collect(),
+                    output: output,
+                })
+            }
+            _ => None
+        }
+    }
+}
+
+impl Item {
+    /// Constructs an `Item` from a parsed item and its source code.
+    fn from_ast(item: &ast::Item, source: String) -> Item {
+        let kind = match item.node {
+            ItemConst(..) => ItemKind::Const,
+            ItemEnum(..) => ItemKind::Enum,
+            ItemForeignMod(..) => ItemKind::ExternBlock,
+            ItemFn(..) => ItemKind::Fn,
+            ItemImpl(_, _, Some(_), _, _) => ItemKind::TraitImpl,
+            ItemImpl(..) => ItemKind::Impl,
+            ItemMac(..) => ItemKind::Macro,
+            ItemMod(..) => ItemKind::Mod,
+            ItemStatic(..) => ItemKind::Static,
+            ItemStruct(..) => ItemKind::Struct,
+            ItemTrait(..) => ItemKind::Trait,
+            ItemTy(..) => ItemKind::Type,
+        };
+
+        let name = match item.node {
+            ItemImpl(_, _, _, ref ty, _) => match ty.node {
+                TyPath(ref path, _) => path.segments.last()
+                    .map(|seg| token::get_ident(seg.identifier).get().to_string()),
+                _ => None,
+            },
+            ItemForeignMod(..) => None,
+            _ => {
+                let name = token::get_ident(item.ident);
+                if name.get().is_empty() {
+                    None
+                } else {
+                    Some(name.get().to_string())
+                }
+            }
+        };
+
+        Item{
+            kind: kind,
+            name: name,
+            source: source,
+            fn_types: FnTypes::from_ast(item),
+            fn_sig: FnSig::from_ast(item),
+        }
+    }
+
+    /// Constructs an `Item` for a macro invocation with no name.
+    fn macro_invocation(source: String) -> Item {
+        Item{
+            kind: ItemKind::Macro,
+            name: None,
+            source: source,
+            fn_types: None,
+            fn_sig: None,
+        }
+    }
+}
+
+/// `let` declaration at the top level of an input program
+#[deriving(Clone, Show)]
+pub struct Binding {
+    /// Names bound by the declaration
+    pub names: Vec<String>,
+    /// Names referenced by the initializer expression
+    pub refs: Vec<String>,
+    /// Whether the declaration can be evaluated again in a later program;
+    /// i.e. it has an initializer expression without apparent side effects
+    pub replayable: bool,
+    /// Declaration source code
+    pub source: String,
+}
+
+impl Binding {
+    /// Constructs a `Binding` from a parsed `let` declaration and its source code.
+    fn from_ast(local: &ast::Local, source: String) -> Binding {
+        let mut names = Vec::new();
+
+        walk_pat(&*local.pat, |p| {
+            if let PatIdent(_, ref ident, _) = p.node {
+                names.push(token::get_ident(ident.node).get().to_string());
+            }
+            true
+        });
+
+        let mut v = InitVisitor{
+            refs: Vec::new(),
+            side_effects: false,
+        };
+
+        if let Some(ref init) = local.init {
+            v.visit_expr(&**init);
+        }
+
+        Binding{
+            names: names,
+            refs: v.refs,
+            replayable: local.init.is_some() && !v.side_effects,
+            source: source,
+        }
+    }
+}
+
+/// Macros which are known not to have side effects
+static PURE_MACROS: &'static [&'static str] = &[
+    "column",
+    "concat",
+    "env",
+    "file",
+    "format",
+    "line",
+    "option_env",
+    "stringify",
+    "vec",
+];
+
+/// Associated functions which construct values without side effects;
+/// e.g. `Vec::new()`
+static PURE_FNS: &'static [&'static str] = &[
+    "new",
+    "with_capacity",
+];
+
+/// Methods which convert or copy values without side effects
+static PURE_METHODS: &'static [&'static str] = &[
+    "as_slice",
+    "clone",
+    "len",
+    "to_owned",
+    "to_string",
+];
+
+/// Examines the initializer expression of a `let` declaration
+struct InitVisitor {
+    /// Names referenced by the expression
+    refs: Vec<String>,
+    /// Whether the expression may have side effects
+    side_effects: bool,
+}
+
+impl<'v> Visitor<'v> for InitVisitor {
+    fn visit_expr(&mut self, e: &'v ast::Expr) {
+        match e.node {
+            ExprPath(ref path) if path.segments.len() == 1 => {
+                self.refs.push(token::get_ident(
+                    path.segments[0].identifier).get().to_string());
+            }
+            ExprAssign(..) | ExprAssignOp(..) | ExprInlineAsm(..) |
+            ExprWhile(..) | ExprWhileLet(..) | ExprForLoop(..) | ExprLoop(..) |
+            ExprRet(..) => {
+                self.side_effects = true;
+            }
+            // Any call may have side effects, except for a call to a
+            // constructor; e.g. `Some(1)`, `Point(1, 2)`, or `Vec::new()`
+            ExprCall(ref f, _) => {
+                let pure = match f.node {
+                    ExprPath(ref path) => path.segments.last().map_or(false, |seg| {
+                        let name = token::get_ident(seg.identifier);
+                        name.get().char_at(0).is_uppercase() ||
+                            (path.segments.len() > 1 &&
+                                PURE_FNS.iter().any(|n| *n == name.get()))
+                    }),
+                    _ => false
+                };
+
+                if !pure {
+                    self.side_effects = true;
+                }
+            }
+            ExprMethodCall(ref ident, _, _) => {
+                let name = token::get_ident(ident.node);
+
+                if !PURE_METHODS.iter().any(|n| *n == name.get()) {
+                    self.side_effects = true;
+                }
+            }
+            _ => ()
+        }
+
+        visit::walk_expr(self, e);
+    }
+
+    fn visit_mac(&mut self, mac: &'v ast::Mac) {
+        let MacInvocTT(ref path, _, _) = mac.node;
+
+        let pure = path.segments.last().map_or(false, |seg| {
+            let name = token::get_ident(seg.identifier);
+            PURE_MACROS.iter().any(|m| *m == name.get())
+        });
+
+        if !pure {
+            self.side_effects = true;
+        }
+    }
+}
+
 /// Represents an input program
 #[deriving(Show)]
 pub struct Input {
@@ -210,9 +779,11 @@ pub struct Input {
     /// Module-level view items (`use`, `extern crate`)
     pub view_items: Vec<(ViewItem, String)>,
     /// Module-level items (`fn`, `enum`, `type`, `struct`, etc.)
-    pub items: Vec<String>,
+    pub items: Vec<Item>,
     /// Inner statements and declarations
     pub statements: Vec<String>,
+    /// `let` declarations, which are also included in `statements`
+    pub bindings: Vec<Binding>,
     /// Whether the final statement (if there are any) is an expression
     /// without a trailing semicolon
     pub last_expr: bool,
@@ -225,6 +796,7 @@ impl Input {
             view_items: Vec::new(),
             items: Vec::new(),
             statements: Vec::new(),
+            bindings: Vec::new(),
             last_expr: false,
         }
     }
@@ -266,30 +838,47 @@ pub fn parse_input(line: &str) -> InputResult {
 /// will result in a value of `More`. Otherwise, these errors will be emitted
 /// and `InputError` will be returned.
 pub fn parse_program(code: &str, filter: bool, filename: Option<&str>) -> InputResult {
+    parse_program_with(code, filter, filename, false)
+}
+
+/// Parses the input of a `.block` command as a program.
+///
+/// Items and statements are classified as they are for any other input.
+/// The end of the block marks the end of input, so errors that indicate
+/// an incomplete input are emitted rather than resulting in `More`.
+pub fn parse_block(code: &str) -> InputResult {
+    parse_program(code, false, None)
+}
+
+/// Parses a line of input as a program, as `parse_program` does,
+/// but discards any error messages rather than printing them.
+pub fn parse_program_quiet(code: &str, filename: Option<&str>) -> InputResult {
+    parse_program_with(code, false, filename, true)
+}
+
+fn parse_program_with(code: &str, filter: bool, filename: Option<&str>,
+        quiet: bool) -> InputResult {
     let (tx, rx) = channel();
 
     let task = Builder::new().stderr(box NullWriter);
 
-    // Items are not returned in data structures; nor are they converted back
-    // into strings. Instead, to preserve user input formatting, we use
-    // byte offsets to return the input as it was received.
-    fn slice(s: &String, lo: BytePos, hi: BytePos) -> String {
-        s.as_slice().slice(lo.0 as uint, hi.0 as uint).to_string()
-    }
-
     let code = code.to_string();
     let filename = filename.unwrap_or("<input>").to_string();
 
     let res = task.spawn(move || {
         let mut input = Input::new();
-        let handler = mk_handler(box ErrorEmitter::new(tx, filter));
+        let emitter = if quiet {
+            ErrorEmitter::quiet(tx)
+        } else {
+            ErrorEmitter::new(tx, filter)
+        };
+        let handler = mk_handler(box emitter);
         let mut sess = new_parse_sess();
 
         sess.span_diagnostic.handler = handler;
 
-        let mut p = filemap_to_parser(&sess,
-            string_to_filemap(&sess, code.to_string(), filename),
-            Vec::new());
+        let filemap = string_to_filemap(&sess, code, filename);
+        let mut p = filemap_to_parser(&sess, filemap.clone(), Vec::new());
 
         // Whether the last statement is an expression without a semicolon
         let mut last_expr = false;
@@ -305,7 +894,7 @@ pub fn parse_program(code: &str, filter: bool, filename: Option<&str>) -> InputR
             let attrs = if p.token == token::Pound {
                 if p.look_ahead(1, |t| *t == token::Not) {
                     let _ = p.parse_attribute(true);
-                    input.attributes.push(slice(&code, lo, p.last_span.hi));
+                    input.attributes.push(slice(&*filemap, lo, p.last_span.hi));
                     continue;
                 }
 
@@ -318,12 +907,17 @@ pub fn parse_program(code: &str, filter: bool, filename: Option<&str>) -> InputR
                 sess.span_diagnostic.handler.fatal("expected item after attributes");
             }
 
+            // `extern crate` declarations, including renamed crates
+            // (e.g. `extern crate "foo" as bar;`), are view items and
+            // must be sorted before any `use` declarations.
             let is_view_item = if p.token.is_keyword(keywords::Use) {
                 true
             } else if p.token.is_keyword(keywords::Extern) {
                 p.look_ahead(1, |t| t.is_keyword(keywords::Crate))
             } else if p.token.is_keyword(keywords::Pub) {
-                p.look_ahead(1, |t| t.is_keyword(keywords::Use))
+                p.look_ahead(1, |t| t.is_keyword(keywords::Use)) ||
+                    (p.look_ahead(1, |t| t.is_keyword(keywords::Extern)) &&
+                    p.look_ahead(2, |t| t.is_keyword(keywords::Crate)))
             } else {
                 false
             };
@@ -338,7 +932,8 @@ pub fn parse_program(code: &str, filter: bool, filename: Option<&str>) -> InputR
 
                 let hi = p.last_span.hi;
 
-                input.view_items.push((vi_ty, slice(&code, lo, hi)));
+                input.view_items.push((vi_ty, slice(&*filemap, lo, hi)));
+                last_expr = false;
             } else {
                 let stmt = p.parse_stmt(attrs);
 
@@ -349,11 +944,13 @@ pub fn parse_program(code: &str, filter: bool, filename: Option<&str>) -> InputR
                         if classify::expr_requires_semi_to_be_stmt(&**e) {
                             p.commit_stmt(&[], &[token::Semi, token::Eof]);
                         }
-                        !p.eat(&token::Semi)
+                        // Block-like expressions which never have a value,
+                        // e.g. `if cond { ... }`, are always statements.
+                        !p.eat(&token::Semi) && !is_unit_block_expr(&**e)
                     }
-                    StmtMac(_, MacStmtWithoutBraces) => {
+                    StmtMac(ref mac, MacStmtWithoutBraces) => {
                         p.expect_one_of(&[], &[token::Semi, token::Eof]);
-                        !p.eat(&token::Semi)
+                        !p.eat(&token::Semi) && !is_macro_rules(&**mac)
                     }
                     StmtMac(_, _) => false,
                     StmtDecl(ref decl, _) => {
@@ -370,18 +967,29 @@ pub fn parse_program(code: &str, filter: bool, filename: Option<&str>) -> InputR
                     _ => false
                 };
 
-                let dest = match stmt.node {
+                let src = slice(&*filemap, lo, hi.unwrap_or(p.last_span.hi));
+
+                match stmt.node {
                     StmtDecl(ref decl, _) => {
                         match decl.node {
-                            DeclLocal(..) => &mut input.statements,
-                            _ => &mut input.items,
+                            DeclLocal(ref local) => {
+                                input.bindings.push(Binding::from_ast(&**local, src.clone()));
+                                input.statements.push(src);
+                            }
+                            DeclItem(ref item) =>
+                                input.items.push(Item::from_ast(&**item, src)),
                         }
                     },
-                    StmtMac(_, MacStmtWithBraces) => &mut input.items,
-                    _ => &mut input.statements,
-                };
-
-                dest.push(slice(&code, lo, hi.unwrap_or(p.last_span.hi)));
+                    StmtMac(_, MacStmtWithBraces) =>
+                        input.items.push(Item::macro_invocation(src)),
+                    // `macro_rules!` defines an item, whatever its delimiters.
+                    // Without braces, it requires a semicolon at item position.
+                    StmtMac(ref mac, _) if is_macro_rules(&**mac) => {
+                        let src = if src.ends_with(";") { src } else { src + ";" };
+                        input.items.push(Item::macro_invocation(src));
+                    }
+                    _ => input.statements.push(src),
+                }
             }
         }
 
@@ -402,6 +1010,224 @@ pub fn parse_program(code: &str, filter: bool, filename: Option<&str>) -> InputR
     }
 }
 
+/// Returns whether the end of the given code lies within a string literal
+/// or block comment, as determined by the lexer; e.g. `r#"{` or `/* }`.
+/// Braces within literals and comments, such as `"{"` or `// {`,
+/// have no effect.
+pub fn ends_within_literal(code: &str) -> bool {
+    let (tx, rx) = channel();
+
+    let task = Builder::new().stderr(box NullWriter);
+
+    let code = code.to_string();
+
+    let res = task.spawn(move || {
+        let handler = mk_handler(box ErrorEmitter::quiet(tx));
+        let mut sess = new_parse_sess();
+
+        sess.span_diagnostic.handler = handler;
+
+        let filemap = string_to_filemap(&sess, code, "<input>".to_string());
+        let mut reader = StringReader::new(&sess.span_diagnostic, filemap);
+
+        while reader.next_token().tok != token::Eof {}
+    }).join();
+
+    // An unterminated literal or comment is reported as a non-fatal error
+    res.is_err() && rx.iter().any(|fatal| !fatal)
+}
+
+/// Returns whether the expression is block-like and never produces a value.
+fn is_unit_block_expr(e: &ast::Expr) -> bool {
+    match e.node {
+        ExprIf(_, _, None) | ExprIfLet(_, _, _, None) |
+        ExprWhile(..) | ExprWhileLet(..) | ExprForLoop(..) | ExprLoop(..) => true,
+        _ => false
+    }
+}
+
+/// Returns whether the macro invocation is `macro_rules!`.
+fn is_macro_rules(mac: &ast::Mac) -> bool {
+    let MacInvocTT(ref path, _, _) = mac.node;
+
+    path.segments.len() == 1 &&
+        token::get_ident(path.segments[0].identifier).get() == "macro_rules"
+}
+
+/// Parses a comma-separated list of expressions.
+/// Returns the source of each expression, or `None` if there are parse errors,
+/// which will be printed to `stderr`.
+pub fn parse_exprs(code: &str) -> Option<Vec<String>> {
+    let (tx, _rx) = channel();
+
+    let task = Builder::new().stderr(box NullWriter);
+
+    let code = code.to_string();
+
+    let res = task.spawn(move || {
+        let handler = mk_handler(box ErrorEmitter::new(tx, false));
+        let mut sess = new_parse_sess();
+
+        sess.span_diagnostic.handler = handler;
+
+        let filemap = string_to_filemap(&sess, code, "<input>".to_string());
+        let mut p = filemap_to_parser(&sess, filemap.clone(), Vec::new());
+
+        let mut exprs = Vec::new();
+
+        while p.token != token::Eof {
+            let lo = p.span.lo;
+            let _ = p.parse_expr();
+
+            exprs.push(slice(&*filemap, lo, p.last_span.hi));
+
+            if !p.eat(&token::Comma) {
+                p.expect(&token::Eof);
+            }
+        }
+
+        exprs
+    }).join();
+
+    res.ok()
+}
+
+/// Parses `<pat>[: <type>] [= <expr>]` as the local declaration of a `let`
+/// statement. Returns the source of the pattern, the type, and the initializer,
+/// or `None` if there are parse errors, which will be printed to `stderr`.
+pub fn parse_let(code: &str) -> Option<(String, Option<String>, Option<String>)> {
+    let (tx, _rx) = channel();
+
+    let task = Builder::new().stderr(box NullWriter);
+
+    let code = format!("let {}", code);
+
+    let res = task.spawn(move || {
+        let handler = mk_handler(box ErrorEmitter::new(tx, false));
+        let mut sess = new_parse_sess();
+
+        sess.span_diagnostic.handler = handler;
+
+        let filemap = string_to_filemap(&sess, code, "<input>".to_string());
+        let mut p = filemap_to_parser(&sess, filemap.clone(), Vec::new());
+
+        // The declaration is parsed by `parse_local`, as in any `let` statement
+        let stmt = p.parse_stmt(Vec::new());
+        p.expect(&token::Eof);
+
+        match stmt.node {
+            StmtDecl(ref decl, _) => match decl.node {
+                DeclLocal(ref local) => {
+                    let ty = match local.ty.node {
+                        TyInfer => None,
+                        _ => Some(slice(&*filemap, local.ty.span.lo, local.ty.span.hi)),
+                    };
+
+                    Some((slice(&*filemap, local.pat.span.lo, local.pat.span.hi), ty,
+                        local.init.as_ref().map(|e| slice(&*filemap, e.span.lo, e.span.hi))))
+                }
+                _ => None
+            },
+            _ => None
+        }
+    }).join();
+
+    res.ok().and_then(|local| local)
+}
+
+/// Returns the names bound by a `use` or `extern crate` declaration.
+/// Glob imports bind no names. If the declaration cannot be parsed,
+/// no names are returned.
+pub fn view_item_names(code: &str) -> Vec<String> {
+    let (tx, _rx) = channel();
+
+    let task = Builder::new().stderr(box NullWriter);
+
+    let code = code.to_string();
+
+    let res = task.spawn(move || {
+        let handler = mk_handler(box ErrorEmitter::quiet(tx));
+        let mut sess = new_parse_sess();
+
+        sess.span_diagnostic.handler = handler;
+
+        let mut p = filemap_to_parser(&sess,
+            string_to_filemap(&sess, code, "<input>".to_string()),
+            Vec::new());
+
+        let attrs = p.parse_outer_attributes();
+        let vitem = p.parse_view_item(attrs);
+
+        let idents = match vitem.node {
+            ViewItemExternCrate(ident, _, _) => vec![ident],
+            ViewItemUse(ref vpath) => match vpath.node {
+                ViewPathSimple(ident, _, _) => vec![ident],
+                ViewPathGlob(..) => vec![],
+                ViewPathList(ref path, ref list, _) => list.iter()
+                    .filter_map(|item| match item.node {
+                        PathListIdent{name, ..} => Some(name),
+                        // `self` binds the last segment of the path
+                        PathListMod{..} => path.segments.last().map(|s| s.identifier),
+                    }).collect(),
+            },
+        };
+
+        idents.into_iter().map(|i| token::get_ident(i).get().to_string()).collect()
+    }).join();
+
+    res.unwrap_or(Vec::new())
+}
+
+/// Parses input as the body of a block and returns a debug representation
+/// of each attribute, view item, statement, and final expression in the AST.
+/// Returns `None` if there are parse errors, which will be printed to `stderr`.
+pub fn parse_ast(code: &str) -> Option<Vec<String>> {
+    let (tx, _rx) = channel();
+
+    let task = Builder::new().stderr(box NullWriter);
+
+    // Identifiers are interned in the parsing task,
+    // so nodes must be formatted before they are returned.
+    let code = format!("{{\n{}\n}}", code);
+
+    let res = task.spawn(move || {
+        let handler = mk_handler(box ErrorEmitter::new(tx, false));
+        let mut sess = new_parse_sess();
+
+        sess.span_diagnostic.handler = handler;
+
+        let mut p = filemap_to_parser(&sess,
+            string_to_filemap(&sess, code, "<input>".to_string()),
+            Vec::new());
+
+        let (attrs, block) = p.parse_inner_attrs_and_block();
+        p.expect(&token::Eof);
+
+        let mut nodes = Vec::new();
+
+        nodes.extend(attrs.iter().map(|a| format!("{}", a)));
+        nodes.extend(block.view_items.iter().map(|v| format!("{}", v)));
+        nodes.extend(block.stmts.iter().map(|s| format!("{}", s)));
+        nodes.extend(block.expr.iter().map(|e| format!("{}", e)));
+
+        nodes
+    }).join();
+
+    res.ok()
+}
+
+// Items are not returned in data structures; nor are they converted back
+// into strings. Instead, to preserve user input formatting, we use
+// byte offsets to return the input as it was received.
+//
+// Offsets index into the source held by the `FileMap`, which may differ
+// from the input; e.g. a leading byte order mark is removed. Spans always
+// fall on UTF-8 character boundaries of this source.
+fn slice(fm: &FileMap, lo: BytePos, hi: BytePos) -> String {
+    let start = fm.start_pos.0 as uint;
+    fm.src.as_slice().slice(lo.0 as uint - start, hi.0 as uint - start).to_string()
+}
+
 /// Filters error messages and reports to a channel
 struct ErrorEmitter {
     /// Sends true for fatal errors; false for `More` errors
@@ -422,6 +1248,16 @@ impl ErrorEmitter {
             filter: filter,
         }
     }
+
+    /// Constructs a new `ErrorEmitter` which will report fatal-ness of errors
+    /// to the given channel, discarding all error messages.
+    fn quiet(tx: Sender<bool>) -> ErrorEmitter {
+        ErrorEmitter{
+            errors: tx,
+            emitter: EmitterWriter::new(box NullWriter, None),
+            filter: true,
+        }
+    }
 }
 
 impl Emitter for ErrorEmitter {
@@ -14,13 +14,18 @@
 
 extern crate getopts;
 extern crate rustc;
+extern crate serialize;
 extern crate syntax;
 
 #[phase(plugin, link)] extern crate log;
 
 use getopts::{optflag, optopt, optmulti, OptGroup};
 
+use std::default::Default;
 use std::io::fs::PathExtensions;
+use std::io::process::Command;
+
+use serialize::json;
 
 pub mod exec;
 pub mod input;
@@ -33,11 +38,15 @@ pub fn run() {
     let opts = &[
         optopt("c", "", "Execute a rusti command and exit", "COMMAND"),
         optopt("e", "", "Execute a one-line program and exit", "PROGRAM"),
+        optflag("", "cargo", "Add library paths of the enclosing Cargo project"),
         optflag("h", "help", "Print this help message and exit"),
         optflag("i", "interactive", "Run rusti interactively even with a file"),
         optflag("v", "version", "Print version and exit"),
         optmulti("L", "", "Add a directory to the library search path", "PATH"),
-        optflag("", "no-rc", "Do not run $HOME/.rustirc.rs"),
+        optflag("", "no-rc", "Do not run $RUSTI_CONFIG or $HOME/.rustirc.rs"),
+        optopt("", "prelude", "Make items in a file available to all input", "FILE"),
+        optopt("", "sysroot", "Override the Rust sysroot; default is from $RUSTI_SYSROOT \
+            or the location of rustc in PATH", "PATH"),
     ];
 
     let matches = match getopts::getopts(args.tail(), opts) {
@@ -63,26 +72,82 @@ pub fn run() {
         !matches.opt_present("c") &&
         !matches.opt_present("e"));
 
-    let addl_libs = matches.opt_strs("L");
+    let mut addl_libs = matches.opt_strs("L");
 
-    let mut repl = repl::Repl::new_with_libs(addl_libs);
+    if matches.opt_present("cargo") {
+        match cargo_lib_paths() {
+            Ok(paths) => addl_libs.extend(paths.into_iter()),
+            Err(e) => {
+                println!("{}: {}", args[0], e);
+                std::os::set_exit_status(1);
+                return;
+            }
+        }
+    }
 
-    if !matches.opt_present("no-rc") {
-        if let Some(p) = std::os::homedir() {
-            let rc = p.join(".rustirc.rs");
-            if rc.is_file() {
-                if !repl.run_file(rc) {
+    let sysroot = match matches.opt_str("sysroot") {
+        Some(sysroot) => {
+            let sysroot = Path::new(sysroot);
+
+            match exec::check_sysroot(&sysroot) {
+                Ok(()) => Ok(sysroot),
+                Err(e) => Err(e),
+            }
+        }
+        None => exec::get_sysroot(),
+    };
+
+    let config = match sysroot {
+        Ok(sysroot) => repl::ReplConfig{
+            libs: addl_libs,
+            sysroot: Some(sysroot),
+            ..Default::default()
+        },
+        Err(e) => {
+            println!("{}: {}", args[0], e);
+            std::os::set_exit_status(1);
+            return;
+        }
+    };
+
+    let mut repl = match matches.opt_str("prelude") {
+        Some(path) => {
+            let res = std::io::File::open(&Path::new(path.as_slice()))
+                .read_to_string().map_err(|e| e.to_string())
+                .and_then(|prelude| repl::Repl::new_with_config_prelude(
+                    config, prelude.as_slice()));
+
+            match res {
+                Ok(repl) => repl,
+                Err(e) => {
+                    println!("{}: {}: {}", args[0], path, e);
                     std::os::set_exit_status(1);
                     return;
                 }
             }
         }
+        None => repl::Repl::new_with_config(config),
+    };
+
+    if let Some(prompt) = std::os::getenv("RUSTI_PROMPT") {
+        repl.set_prompt(prompt);
+    }
+
+    if !matches.opt_present("no-rc") {
+        if let Some(rc) = rc_file() {
+            if !repl.run_file(rc) {
+                std::os::set_exit_status(1);
+                return;
+            }
+        }
     }
 
     if let Some(cmd) = matches.opt_str("c") {
         repl.run_command(cmd.as_slice());
     } else if let Some(expr) = matches.opt_str("e") {
-        repl.eval(expr.as_slice());
+        if !repl.run_code(expr.as_slice()) {
+            std::os::set_exit_status(1);
+        }
     } else if !matches.free.is_empty() {
         let path = Path::new(&matches.free[0]);
 
@@ -92,8 +157,78 @@ pub fn run() {
     }
 
     if interactive {
-        repl.run();
+        // Input piped to `stdin` is run as a whole file
+        if std::io::stdio::stdin_raw().isatty() {
+            repl.run();
+        } else if !repl.run_stdin() {
+            std::os::set_exit_status(1);
+        }
+    }
+}
+
+/// Returns the path of the startup file to run, if one exists.
+/// This is `$RUSTI_CONFIG`, if it is set, or otherwise the first of
+/// `$HOME/.rustirc.rs` and `$HOME/.rustirc` which exists.
+fn rc_file() -> Option<Path> {
+    let paths = match std::os::getenv("RUSTI_CONFIG") {
+        Some(path) => vec![Path::new(path)],
+        None => match std::os::homedir() {
+            Some(home) => vec![home.join(".rustirc.rs"), home.join(".rustirc")],
+            None => Vec::new(),
+        }
+    };
+
+    paths.into_iter().find(|p| p.is_file())
+}
+
+/// Returns library search paths for dependencies of the Cargo project
+/// containing the current directory; i.e. the `deps` directories within
+/// the project's target directory which exist.
+///
+/// The target directory is reported by `cargo metadata`, or if that fails,
+/// is assumed to be `target` beside the project's `Cargo.toml`.
+fn cargo_lib_paths() -> Result<Vec<String>, String> {
+    let mut root = try!(std::os::getcwd().map_err(|e| e.to_string()));
+
+    while !root.join("Cargo.toml").is_file() {
+        if !root.pop() {
+            return Err("could not find `Cargo.toml` in this directory \
+                or any parent directory".to_string());
+        }
     }
+
+    let target = cargo_target_dir(&root).unwrap_or_else(|| root.join("target"));
+
+    Ok(["deps", "debug/deps", "release/deps"].iter()
+        .map(|d| target.join(*d)).filter(|p| p.is_dir())
+        .map(|p| p.display().to_string()).collect())
+}
+
+/// Runs `cargo metadata` within the given project directory and returns
+/// the target directory it reports.
+fn cargo_target_dir(root: &Path) -> Option<Path> {
+    let out = match Command::new("cargo")
+            .args(&["metadata", "--format-version", "1", "--no-deps"])
+            .cwd(root).output() {
+        Ok(out) => out,
+        Err(e) => {
+            debug!("failed to run `cargo metadata`: {}", e);
+            return None;
+        }
+    };
+
+    if !out.status.success() {
+        debug!("`cargo metadata` failed: {}", String::from_utf8_lossy(out.error.as_slice()));
+        return None;
+    }
+
+    let meta = match String::from_utf8(out.output).ok()
+            .and_then(|s| json::from_str(s.as_slice()).ok()) {
+        Some(meta) => meta,
+        None => return None,
+    };
+
+    meta.find("target_directory").and_then(|t| t.as_string()).map(|t| Path::new(t))
 }
 
 /// Returns a version string.
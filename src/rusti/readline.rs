@@ -11,14 +11,49 @@
 extern crate libc;
 
 use std::c_str::CString;
-//use std::c_vec::CVec;
+use std::c_vec::CVec;
+use std::cell::RefCell;
+use std::cmp::min;
+use std::mem::size_of;
+use std::os;
 use std::ptr;
 use std::sync::{Once, ONCE_INIT};
+use std::sync::atomic::{AtomicBool, INIT_ATOMIC_BOOL};
+use std::sync::atomic::Ordering::SeqCst;
 
-use self::libc::{c_char, c_int, /* size_t */};
+use self::libc::{c_char, c_int, size_t};
+use self::libc::consts::os::posix88::{EINTR, SIGINT, STDIN_FILENO, STDOUT_FILENO};
 
 static INIT_READLINE: Once = ONCE_INIT;
 
+/// Whether input is currently being read, through `readline` or otherwise
+static READING: AtomicBool = INIT_ATOMIC_BOOL;
+/// Whether an interrupt was received since the last line was returned
+static INTERRUPTED: AtomicBool = INIT_ATOMIC_BOOL;
+/// Whether an interrupt was received by the signal handler and
+/// has not yet been handled
+static PENDING: AtomicBool = INIT_ATOMIC_BOOL;
+/// Whether an interrupt requested that `read_line` signal end-of-file
+static EXIT: AtomicBool = INIT_ATOMIC_BOOL;
+/// Prompt displayed after input is interrupted
+static mut INTERRUPT_PROMPT: *const c_char = 0 as *const c_char;
+
+/// Value of `SIG_DFL`, the default signal disposition
+const SIG_DFL: size_t = 0;
+
+thread_local!(static COMPLETIONS: RefCell<Completions> = RefCell::new(Completions{
+    commands: Vec::new(),
+    names: Vec::new(),
+}));
+
+/// Candidates for text completion
+struct Completions {
+    /// Command names, without the leading `.`
+    commands: Vec<String>,
+    /// Names defined in the current session
+    names: Vec<String>,
+}
+
 /// Readline completion function. Called to perform text completion.
 /// Takes arguments `text` (segment of input being completed),
 /// `start` (start of input within line buffer),
@@ -35,17 +70,63 @@ extern "C" {
 
     #[link_name = "add_history"]
     fn rl_add_history(line: *const c_char);
+    #[link_name = "clear_history"]
+    fn rl_clear_history();
+    #[link_name = "read_history"]
+    fn rl_read_history(filename: *const c_char) -> c_int;
     #[link_name = "readline"]
     fn rl_readline(prompt: *const c_char) -> *const c_char;
+    #[link_name = "stifle_history"]
+    fn rl_stifle_history(max: c_int);
     fn rl_insert_text(text: *const c_char) -> c_int;
+
+    static mut rl_catch_signals: c_int;
+    static mut rl_done: c_int;
+    static rl_line_buffer: *const c_char;
+    static mut rl_signal_event_hook: Option<extern "C" fn() -> c_int>;
+
+    fn rl_on_new_line() -> c_int;
+    fn rl_redisplay();
+    fn rl_replace_line(text: *const c_char, clear_undo: c_int);
+    fn rl_set_prompt(prompt: *const c_char) -> c_int;
+}
+
+extern "C" {
+    fn raise(sig: c_int) -> c_int;
+    fn signal(sig: c_int, handler: size_t) -> size_t;
+    fn siginterrupt(sig: c_int, flag: c_int) -> c_int;
 }
 
 fn init_readline() {
     unsafe {
         rl_attempted_completion_function = completion_fn;
+
+        // Interrupts are handled by `interrupt_fn` rather than readline.
+        // A read interrupted by the signal returns, rather than restarting,
+        // so that readline calls `signal_event_fn`.
+        rl_catch_signals = 0;
+        rl_signal_event_hook = Some(signal_event_fn);
+        signal(SIGINT, interrupt_fn as size_t);
+        siginterrupt(SIGINT, 1);
+    }
+}
+
+/// Sets the prompt displayed when an interrupt discards the current input.
+pub fn set_interrupt_prompt(prompt: &str) {
+    unsafe {
+        if !INTERRUPT_PROMPT.is_null() {
+            libc::free(INTERRUPT_PROMPT as *mut libc::c_void);
+        }
+        INTERRUPT_PROMPT = prompt.to_c_str().into_inner();
     }
 }
 
+/// Returns whether an interrupt was received while reading the last line
+/// returned by `read_line`, or since then, and clears the interrupt state.
+pub fn take_interrupt() -> bool {
+    INTERRUPTED.swap(false, SeqCst)
+}
+
 /// Pushes a single line into `readline` history.
 pub fn push_history(line: &str) {
     line.with_c_str(|s| {
@@ -53,23 +134,153 @@ pub fn push_history(line: &str) {
     });
 }
 
+/// Removes all lines from `readline` history.
+pub fn clear_history() {
+    unsafe { rl_clear_history() };
+}
+
+/// Limits `readline` history to the given number of lines,
+/// removing the oldest lines in excess of it.
+pub fn set_history_size(size: uint) {
+    unsafe { rl_stifle_history(size as c_int) };
+}
+
+/// Loads `readline` history from the given file.
+/// Returns `true` if the file was read successfully.
+pub fn load_history(path: &Path) -> bool {
+    path.with_c_str(|s| unsafe { rl_read_history(s) } == 0)
+}
+
+/// Sets the candidates for text completion. Text beginning with `.` at the
+/// start of a line is completed against `commands`; other text is completed
+/// against `names`.
+pub fn set_completions(commands: Vec<String>, names: Vec<String>) {
+    COMPLETIONS.with(|c| *c.borrow_mut() = Completions{
+        commands: commands,
+        names: names,
+    });
+}
+
 /// Reads a line from the input stream. The trailing newline is truncated.
 /// Returns `None` if end-of-file is signaled.
 pub fn read_line(prompt: &str) -> Option<String> {
     INIT_READLINE.doit(init_readline);
 
+    PENDING.store(false, SeqCst);
+    READING.store(true, SeqCst);
     let sp = prompt.with_c_str(|p| unsafe { rl_readline(p) });
+    READING.store(false, SeqCst);
 
     if sp.is_null() {
         None
     } else {
         let cs = unsafe { CString::new(sp as *const i8, true) };
+
+        if EXIT.swap(false, SeqCst) {
+            return None;
+        }
+
         Some(cs.as_str().expect("not UTF-8 input").to_string())
     }
 }
 
+/// Reads a line directly from `stdin`, without line editing.
+/// The trailing newline is truncated. Returns `None` if end-of-file is
+/// reached or if reading is interrupted; in the latter case,
+/// `take_interrupt` will return `true`.
+pub fn read_raw_line() -> Option<String> {
+    INIT_READLINE.doit(init_readline);
+
+    let mut buf = Vec::new();
+    let mut b = 0u8;
+
+    PENDING.store(false, SeqCst);
+    READING.store(true, SeqCst);
+
+    let mut res = false;
+
+    loop {
+        let n = unsafe {
+            libc::read(STDIN_FILENO, &mut b as *mut u8 as *mut libc::c_void, 1)
+        };
+
+        if n == 1 {
+            if b == b'\n' {
+                res = true;
+                break;
+            }
+            buf.push(b);
+        } else if n == 0 {
+            res = !buf.is_empty();
+            break;
+        } else if os::errno() != EINTR as uint {
+            break;
+        } else if PENDING.swap(false, SeqCst) {
+            INTERRUPTED.store(true, SeqCst);
+            break;
+        }
+    }
+
+    READING.store(false, SeqCst);
+
+    if res {
+        String::from_utf8(buf).ok()
+    } else {
+        None
+    }
+}
+
+/// Signal handler for `SIGINT`. While reading input, the interrupt is
+/// recorded and handled once the interrupted read returns.
+/// At any other time, the signal terminates the process.
+///
+/// Only async-signal-safe operations may be performed here.
+extern "C" fn interrupt_fn(_sig: c_int) {
+    if READING.load(SeqCst) {
+        PENDING.store(true, SeqCst);
+    } else {
+        unsafe {
+            signal(SIGINT, SIG_DFL);
+            raise(SIGINT);
+        }
+    }
+}
+
+/// Called by readline, outside of the signal handler, when reading input
+/// is interrupted by a signal. Discards the current line and displays
+/// a fresh prompt. A second interrupt at an empty prompt signals end-of-file.
+extern "C" fn signal_event_fn() -> c_int {
+    if !PENDING.swap(false, SeqCst) {
+        return 0;
+    }
+
+    unsafe {
+        let empty = rl_line_buffer.is_null() || *rl_line_buffer == 0;
+
+        if empty && INTERRUPTED.load(SeqCst) {
+            EXIT.store(true, SeqCst);
+            rl_done = 1;
+            return 0;
+        }
+
+        INTERRUPTED.store(true, SeqCst);
+
+        libc::write(STDOUT_FILENO, b"\n".as_ptr() as *const libc::c_void, 1);
+
+        if !INTERRUPT_PROMPT.is_null() {
+            rl_set_prompt(INTERRUPT_PROMPT);
+        }
+
+        rl_replace_line(b"\0".as_ptr() as *const c_char, 0);
+        rl_on_new_line();
+        rl_redisplay();
+    }
+
+    0
+}
+
 extern "C" fn completion_fn(text: *const c_char,
-        _start: c_int, _end: c_int) -> *mut *const c_char {
+        start: c_int, _end: c_int) -> *mut *const c_char {
     unsafe {
         // Prevent readline from calling its default completion function
         // if this function returns NULL.
@@ -80,32 +291,75 @@ extern "C" fn completion_fn(text: *const c_char,
 
     debug!("completion fn on \"{}\"", text);
 
+    let text = match text.as_str() {
+        Some(text) => text,
+        None => return ptr::null_mut(),
+    };
+
     // Tab with no text inserts indentation
     if text.is_empty() {
         "    ".with_c_str(|s| unsafe { rl_insert_text(s) });
+        return ptr::null_mut();
     }
 
-    // TODO: Completion stuff
+    let matches = COMPLETIONS.with(|c| {
+        let c = c.borrow();
 
-    // For now, just return NULL.
-    ptr::null_mut()
+        if start == 0 && text.starts_with(".") {
+            c.commands.iter()
+                .filter(|cmd| cmd.starts_with(text.slice_from(1)))
+                .map(|cmd| format!(".{}", cmd))
+                .collect::<Vec<_>>()
+        } else {
+            c.names.iter()
+                .filter(|name| name.starts_with(text))
+                .map(|name| name.clone())
+                .collect()
+        }
+    });
+
+    if matches.is_empty() {
+        return ptr::null_mut();
+    }
+
+    // The first element is the substitution text. If there are multiple
+    // matches, readline lists the remaining elements.
+    let mut subs = vec![common_prefix(matches.as_slice()).to_string()];
+
+    if matches.len() > 1 {
+        subs.extend(matches.into_iter());
+    }
 
-    /*
-    // This is how a C array should be created.
     unsafe {
-        let size: size_t = std::mem::size_of::<*const c_char>();
-        let n: size_t = 2;
-        let buf = libc::calloc(n, size);
+        let n = subs.len() + 1;
+        let buf = libc::calloc(n as size_t, size_of::<*const c_char>() as size_t);
         let mut vec = CVec::new(buf as *mut *const c_char, n);
 
         {
             let s = vec.as_mut_slice();
 
-            s[0] = "".to_c_str().into_inner();
+            for (i, sub) in subs.iter().enumerate() {
+                s[i] = sub.to_c_str().into_inner();
+            }
             // Last element remains NULL
         }
 
         vec.into_inner()
     }
-    */
+}
+
+/// Returns the longest prefix common to all given strings.
+fn common_prefix(strs: &[String]) -> &str {
+    let first = strs[0].as_slice();
+    let mut end = first.len();
+
+    for s in strs.slice_from(1).iter() {
+        let common = first.char_indices().zip(s.as_slice().chars())
+            .take_while(|&((_, a), b)| a == b)
+            .last().map_or(0, |((i, ch), _)| i + ch.len_utf8());
+
+        end = min(end, common);
+    }
+
+    first.slice_to(end)
 }
@@ -8,22 +8,70 @@
 
 //! Runs Rust code in an encapsulated environment
 
-use std::io::File;
-use std::io::stdio::stdin_raw;
-use std::mem::transmute;
+use std::any::Any;
+use std::cmp::min;
+use std::default::Default;
+use std::mem::replace;
+use std::io::{ChanReader, ChanWriter, File, TempDir, Timer};
+use std::io::fs::{PathExtensions, readdir};
+use std::io::process::{Command, InheritFd};
+use std::io::stdio::{set_stdout, stderr, stdin_raw, stdout, stdout_raw};
 use std::os;
+use std::rand;
+use std::rc::Rc;
+use std::thread::Builder;
+use std::time::Duration;
 
-use super::exec::ExecutionEngine;
-use super::input::{parse_command, parse_program};
-use super::input::{FileReader, Input, InputReader, ViewItem};
+use super::version;
+use super::exec::{AnalysisError, ExecutionEngine, get_sysroot};
+use super::input::{parse_ast, parse_command, parse_exprs, parse_input, parse_program};
+use super::input::{parse_let, parse_program_quiet, view_item_names};
+use super::input::{Binding, FileReader, Input, InputReader, Item, ItemKind, ViewItem};
+use super::input::DEFAULT_HISTORY_SIZE;
 use super::input::InputResult::*;
 
-use super::rustc::middle::ty;
+use super::rustc::llvm::ModuleRef;
+use super::rustc::middle::{def, infer, traits};
+use super::rustc::middle::subst::Substs;
+use super::rustc::middle::ty::{mod, AsPredicate};
+use super::rustc::session::config::OptLevel;
 use super::rustc::util::ppaux::Repr;
 
-use super::syntax::{ast, codemap, visit};
-use super::syntax::ast::Stmt_::StmtSemi;
+use super::syntax::{abi, ast, codemap, visit};
+use super::syntax::ast::Decl_::DeclLocal;
+use super::syntax::ast::Expr_::{ExprAgain, ExprBlock, ExprBreak, ExprCall};
+use super::syntax::ast::Expr_::{ExprMethodCall, ExprParen, ExprRet};
+use super::syntax::ast::Pat_::PatIdent;
+use super::syntax::ast::Stmt_::{StmtDecl, StmtSemi};
+use super::syntax::ast::ViewItem_::ViewItemUse;
+use super::syntax::ast::ViewPath_::ViewPathSimple;
+use super::syntax::ast_util::walk_pat;
 use super::syntax::parse::token;
+use super::syntax::print::pprust;
+
+/// Number of times `.bench` runs an expression, unless another is given
+const BENCH_ITERATIONS: uint = 1000;
+/// Number of history entries shown by `.history`, unless another is given
+const HISTORY_COUNT: uint = 20;
+
+/// ANSI escape sequence beginning a printed value
+const RESULT_COLOR: &'static str = "\x1b[32m";
+/// ANSI escape sequence beginning an error message
+const ERROR_COLOR: &'static str = "\x1b[31m";
+/// ANSI escape sequence restoring the default color
+const RESET_COLOR: &'static str = "\x1b[0m";
+
+/// Codes of diagnostics for a name imported more than once (E0252),
+/// an import conflicting with an item (E0255), and a method call with
+/// multiple applicable methods (E0034)
+static IMPORT_CONFLICT_CODES: &'static [&'static str] = &["E0034", "E0252", "E0255"];
+
+/// Paths of traits by which the value of an expression may be printed,
+/// in order of preference, with the format string using each
+static PRINT_TRAITS: &'static [(&'static str, &'static str)] = &[
+    ("std::fmt::String", "{}"),
+    ("std::fmt::Show", "{:?}"),
+];
 
 /// Starting prompt
 const DEFAULT_PROMPT: &'static str = "rusti=> ";
@@ -32,79 +80,705 @@ const MORE_PROMPT: &'static str = "rusti.> ";
 /// Prompt when a `.block` command is in effect
 const BLOCK_PROMPT: &'static str = "rusti+> ";
 
-// TODO: Implement commands:
-//     def <name>; shows the definition of type or fn
-//     doc <name>; links to rustdoc page for name
-//     help; lists commands and their uses
+/// List of command names, argument syntax, and descriptions
+static COMMANDS: &'static [(&'static str, Option<&'static str>, &'static str)] = &[
+    ("allow", Some("<lint>..."), "Allow lints for the next input only"),
+    ("ast", Some("<code>"), "Show how code is parsed"),
+    ("bench", Some("[count] <expr>"), "Run an expression repeatedly and show time per run"),
+    ("block", None, "Run multiple lines of Rust code; end with `.` or `;;`"),
+    ("cd", Some("<dir>"), "Change the directory used to resolve file paths"),
+    ("cfg", Some("[flag]"), "Show cfg flags or toggle a flag for later inputs"),
+    ("crate", Some("<path>"), "Add a directory to the library search path"),
+    ("debug", Some("[on|off]"), "Show the compiled program when compilation fails"),
+    ("def", Some("<name>"), "Show the definition of an item"),
+    ("deny", Some("<lint>..."), "Deny lints for the next input only"),
+    ("doc", Some("<path>"), "Show a link to documentation for an item"),
+    ("edit", None, "Edit session items in `$EDITOR`"),
+    ("env", None, "Show the sysroot, library search paths, and cfg flags"),
+    ("expr", Some("[code]"), "Treat the final statement of input as an expression"),
+    ("gc", None, "Remove compiled code which is no longer running"),
+    ("get", Some("<name>"), "Show the value of a `#[no_mangle]` static"),
+    ("help", Some("[command]"), "Show help for commands"),
+    ("history", Some("[n|clear]"), "Show the last n lines of input history or clear history"),
+    ("import", Some("<path>"), "Import a name into the session, as with `use`"),
+    ("let", Some("<name> = <expr>"), "Create or assign a persistent mutable binding"),
+    ("lints", Some("[lint]"), "Show allowed lints or toggle whether a lint is allowed"),
+    ("load", Some("<file>"), "Load a Rust source file into the session"),
+    ("load-expr", Some("<file>"), "Run a Rust source file and print its final value"),
+    ("opt", Some("[0-3]"), "Show or set the optimization level of compiled code"),
+    ("paste", None, "Run pasted code; end with `.end`"),
+    ("pin", Some("[code]"), "Keep compiled code of the next input from being removed"),
+    ("print", None, "Show the program compiled for each input"),
+    ("pwd", None, "Show the directory used to resolve file paths"),
+    ("reload", None, "Recompile session items into a single fresh module"),
+    ("reset", Some("[attrs|bindings|imports|items]"),
+        "Remove all or some of the session state"),
+    ("rm-import", Some("<name>"), "Remove session imports of a name or path"),
+    ("run", Some("<fn>"), "Call a function taking no arguments and print its result"),
+    ("save", Some("<file>"), "Save session items to a file; `.save!` overwrites"),
+    ("search", Some("<text>"), "Show imports, items, and bindings containing text"),
+    ("stmt", Some("[code]"), "Treat the final statement of input as a statement"),
+    ("time", Some("<code>"), "Run code and show time spent compiling and running"),
+    ("timeout", Some("[time|off]"), "Show or set a time limit for running code"),
+    ("trace", Some("[on|off]"), "Show time spent in each phase of compilation"),
+    ("type", Some("<expr>"), "Display the type of an expression"),
+    ("undo", None, "Remove items and bindings added by the last input"),
+    ("version", None, "Show versions of rusti and rustc"),
+    ("which", Some("<name>"), "Show which compiled module a symbol is found in"),
+];
 
-/// List of command names
-static COMMANDS: &'static [&'static str] = &[
-    "block",
-    "type",
+/// Abbreviations resolving to a command, though they are a prefix
+/// of more than one command name
+static ABBREVIATIONS: &'static [(&'static str, &'static str)] = &[
+    ("b", "block"),
+    ("h", "help"),
+    ("r", "reset"),
+    ("t", "type"),
 ];
 
+/// Restores the `stdout` replaced by `set_stdout` when dropped,
+/// even if the current task is panicking
+struct StdoutGuard {
+    /// `stdout` of the task before it was replaced, if it had one
+    prev: Option<Box<Writer + Send>>,
+}
+
+impl Drop for StdoutGuard {
+    fn drop(&mut self) {
+        match self.prev.take() {
+            Some(w) => { set_stdout(w); }
+            None => { set_stdout(box stdout()); }
+        }
+    }
+}
+
+/// Result of looking up a command by what may be an abbreviated prefix
+#[deriving(Clone, PartialEq, Eq, Show)]
+enum CommandLookup {
+    /// Name of the single command matched
+    Found(&'static str),
+    /// Names of all commands beginning with an ambiguous prefix
+    Ambiguous(Vec<&'static str>),
+    /// No command matched
+    NotFound,
+}
+
+/// Determines how the value of the final expression of an input is handled
+#[deriving(Copy, PartialEq, Eq)]
+enum ExprMode {
+    /// Print the value to `stdout`
+    Print,
+    /// Return the value to the host
+    Return,
+}
+
+/// Outcome of evaluating input with `Repl::eval`
+#[deriving(Copy, PartialEq, Eq, Show)]
+pub enum EvalResult {
+    /// Input was compiled and run
+    Success,
+    /// Input is incomplete; e.g. it contains unclosed delimiters
+    Incomplete,
+    /// Input could not be parsed
+    ParseError,
+    /// Input failed to compile
+    CompileError,
+}
+
+/// Determines how the value of the final expression of an input is printed
+enum PrintFormat {
+    /// Formatted with the given format string; e.g. `{}`
+    Format(&'static str),
+    /// Not formatted; the given type is printed in place of the value
+    Type(String),
+}
+
+/// Overrides parser classification of the final statement of an input
+#[deriving(Copy, PartialEq, Eq)]
+enum StmtMode {
+    /// Final statement is an expression whose value is printed
+    Expr,
+    /// Final statement is not printed
+    Stmt,
+}
+
+/// Persistent state of a `Repl` at some point in time
+struct Snapshot {
+    /// Number of attributes
+    attributes: uint,
+    /// Number of view items
+    view_items: uint,
+    /// Items; these are cloned because adding an item
+    /// may replace an earlier definition
+    items: Vec<Item>,
+    /// `let` declarations; these are cloned because adding a binding
+    /// may remove earlier bindings
+    bindings: Vec<Binding>,
+    /// `.let` bindings; these are cloned because adding a binding
+    /// replaces an earlier binding of the same name
+    statics: Vec<StaticBinding>,
+}
+
+/// Modules in which session functions are compiled once, so that later
+/// inputs need not compile their bodies again
+struct BaseModule {
+    /// Modules in the order compiled; each holds the functions which
+    /// were added since the one before it
+    modules: Vec<ModuleRef>,
+    /// Source of each function item compiled into a module and
+    /// the symbol through which it is called
+    symbols: Vec<(String, String)>,
+    /// Source of each session item when the last module was compiled
+    items: Vec<String>,
+    /// true if code which may call into the modules has been kept,
+    /// as when an input spawns a thread; the modules are then never removed
+    kept: bool,
+}
+
+/// Mutable binding created by `.let`, whose value is held in a `static mut`
+#[deriving(Clone)]
+struct StaticBinding {
+    /// Name of the binding
+    name: String,
+    /// Type of the value
+    ty: String,
+    /// Symbol name of the `static mut`
+    sym: String,
+}
+
 /// Executes input code and maintains state of persistent items.
 pub struct Repl {
     engine: ExecutionEngine,
+    /// Additional library search paths
+    libs: Vec<String>,
     /// Module-level attributes applied to every program
     attributes: Vec<String>,
     /// View items compiled into every program
     view_items: Vec<(ViewItem, String)>,
-    /// Items compiled into every program
-    /// TODO: When type/def-injection is implemented,
-    /// it will not be necessary to re-compile all functions on every input.
-    items: Vec<String>,
+    /// Items compiled into every program. Functions compiled into the
+    /// base are replaced by calls into it.
+    items: Vec<Item>,
+    /// Modules holding compiled session functions, if there are any
+    base: Option<BaseModule>,
+    /// Number of base modules compiled, used to give each unique symbols
+    base_count: uint,
+    /// Number of inputs compiled, used to give each a unique entry point;
+    /// also used to give each static created by `.let` a unique symbol
+    run_count: uint,
+    /// true if the most recently run input panicked or timed out
+    panicked: bool,
+    /// `let` declarations evaluated at the start of every program
+    bindings: Vec<Binding>,
+    /// Bindings created by `.let`
+    statics: Vec<StaticBinding>,
+    /// Persistent state preceding each input which added to it
+    undo_stack: Vec<Snapshot>,
     /// true if the next input should be a block
     read_block: bool,
+    /// true if the next input should be read as pasted code
+    read_paste: bool,
+    /// Classification of the final statement of the next input, if forced
+    stmt_mode: Option<StmtMode>,
+    /// true if the module compiled for the next input should be pinned
+    pin_next: bool,
+    /// Crate attributes applied only to the next input
+    transient_attrs: Vec<String>,
+    /// Starting prompt
+    prompt: String,
+    /// Prompt when further input is being read
+    more_prompt: String,
+    /// Prompt when a `.block` command is in effect
+    block_prompt: String,
+    /// Maximum number of lines kept in input history
+    history_size: uint,
+    /// Whether to remove modules after execution, when it is safe to do so
+    free_modules: bool,
+    /// Maximum time to wait for a program to run, if any
+    timeout: Option<Duration>,
+    /// Whether to print the program compiled for an input which fails to compile
+    debug: bool,
+    /// Lints allowed in every program
+    allowed_lints: Vec<String>,
+    /// Whether values and errors are printed in color
+    color: bool,
+    /// Interactive input reader, while `run` is in progress
+    reader: Option<InputReader>,
+    /// Directory against which relative file paths are resolved
+    cwd: Path,
+    /// Suffix appended to names of functions generated by rusti,
+    /// making them unlikely to conflict with names of user items
+    symbol_suffix: String,
+}
+
+/// Settings used to construct a `Repl`
+#[deriving(Clone)]
+pub struct ReplConfig {
+    /// Additional library search paths
+    pub libs: Vec<String>,
+    /// Sysroot in which Rust libraries are found; if `None`, it is found
+    /// as by `exec::get_sysroot`
+    pub sysroot: Option<Path>,
+    /// Optimization level of compiled code
+    pub opt_level: OptLevel,
+    /// Additional configuration flags; e.g. `foo` or `feature="foo"`
+    pub cfgs: Vec<String>,
+    /// Lints allowed in every program
+    pub allowed_lints: Vec<String>,
+    /// Starting prompt
+    pub prompt: String,
+    /// Prompt when further input is being read
+    pub more_prompt: String,
+    /// Prompt when a `.block` command is in effect
+    pub block_prompt: String,
+    /// Maximum number of lines kept in input history
+    pub history_size: uint,
+}
+
+impl Default for ReplConfig {
+    fn default() -> ReplConfig {
+        ReplConfig{
+            libs: Vec::new(),
+            sysroot: None,
+            opt_level: OptLevel::No,
+            cfgs: Vec::new(),
+            allowed_lints: vec!["dead_code".to_string(), "unused_imports".to_string()],
+            prompt: DEFAULT_PROMPT.to_string(),
+            more_prompt: MORE_PROMPT.to_string(),
+            block_prompt: BLOCK_PROMPT.to_string(),
+            history_size: DEFAULT_HISTORY_SIZE,
+        }
+    }
 }
 
 /// Looks up a command name by what may be an abbreviated prefix.
-/// Returns the full command name. e.g. `"b"` => `Some("block")`
-fn lookup_command(name: &str) -> Option<&'static str> {
-    for cmd in COMMANDS.iter() {
-        if cmd.starts_with(name) {
-            return Some(*cmd);
+/// Returns the full command name. e.g. `"b"` => `Found("block")`
+///
+/// A complete command name is always found, even if it is a prefix of
+/// another, as with `load` and `load-expr`.
+fn lookup_command(name: &str) -> CommandLookup {
+    if let Some(&(cmd, _, _)) = COMMANDS.iter().find(|&&(cmd, _, _)| cmd == name) {
+        return CommandLookup::Found(cmd);
+    }
+
+    for &(abbr, cmd) in ABBREVIATIONS.iter() {
+        if abbr == name {
+            return CommandLookup::Found(cmd);
         }
     }
-    None
+
+    let matches = COMMANDS.iter().map(|&(cmd, _, _)| cmd)
+        .filter(|cmd| cmd.starts_with(name)).collect::<Vec<_>>();
+
+    match matches.len() {
+        0 => CommandLookup::NotFound,
+        1 => CommandLookup::Found(matches[0]),
+        _ => CommandLookup::Ambiguous(matches),
+    }
+}
+
+/// Prints the commands which an ambiguous command prefix may refer to.
+fn print_ambiguous(name: &str, cmds: &[&str]) {
+    let cmds = cmds.iter().map(|cmd| format!(".{}", cmd)).collect::<Vec<_>>();
+
+    println!("ambiguous command `{}`; could be {}", name, cmds.connect(", "));
+}
+
+/// Returns the names of all commands.
+fn command_names() -> Vec<String> {
+    COMMANDS.iter().map(|&(name, _, _)| name.to_string()).collect()
+}
+
+/// Prints the usage and description of a single command.
+fn print_command_help(name: &str, args: Option<&str>, desc: &str) {
+    let usage = match args {
+        Some(args) => format!(".{} {}", name, args),
+        None => format!(".{}", name),
+    };
+
+    println!("{:<20} {}", usage, desc);
 }
 
 impl Repl {
     /// Constructs a new `Repl`.
     pub fn new() -> Repl {
-        Repl::new_with_libs(Vec::new())
+        Repl::new_with_config(Default::default())
     }
 
     /// Constructs a new `Repl` with additional library lookup paths.
     pub fn new_with_libs(libs: Vec<String>) -> Repl {
+        Repl::new_with_config(ReplConfig{
+            libs: libs,
+            ..Default::default()
+        })
+    }
+
+    /// Constructs a new `Repl` with the given settings.
+    ///
+    /// # Panics
+    ///
+    /// If `config.sysroot` is `None` and the sysroot cannot be found.
+    pub fn new_with_config(config: ReplConfig) -> Repl {
+        let engine = match config.sysroot {
+            Some(ref sysroot) => ExecutionEngine::new_with_sysroot(
+                config.libs.clone(), sysroot.clone()),
+            None => ExecutionEngine::new(config.libs.clone()),
+        };
+
+        Repl::with_config(engine, config)
+    }
+
+    /// Constructs a new `Repl` with additional library lookup paths and
+    /// prelude code. Attributes, imports, and items in the prelude are
+    /// compiled into the execution engine at construction and are available
+    /// to every input.
+    ///
+    /// Returns an error if the prelude contains statements or if it fails
+    /// to parse or compile.
+    pub fn new_with_prelude(libs: Vec<String>, prelude: &str) -> Result<Repl, String> {
+        Repl::new_with_config_prelude(ReplConfig{
+            libs: libs,
+            ..Default::default()
+        }, prelude)
+    }
+
+    /// Constructs a new `Repl` with the given settings and prelude code,
+    /// as with `new_with_prelude`.
+    ///
+    /// Returns an error if the prelude contains statements, if it fails
+    /// to parse or compile, or if `config.sysroot` is `None` and the sysroot
+    /// cannot be found.
+    pub fn new_with_config_prelude(config: ReplConfig, prelude: &str)
+            -> Result<Repl, String> {
+        let input = match parse_program(prelude, false, Some("<prelude>")) {
+            Program(input) => input,
+            InputError(Some(e)) => return Err(e.into_owned()),
+            _ => return Err("prelude failed to parse".to_string()),
+        };
+
+        if !input.statements.is_empty() {
+            return Err("prelude may contain only attributes, imports, and items".to_string());
+        }
+
+        let sysroot = match config.sysroot {
+            Some(ref sysroot) => sysroot.clone(),
+            None => try!(get_sysroot()),
+        };
+
+        let engine = ExecutionEngine::new_with_sysroot(config.libs.clone(), sysroot);
+        let mut repl = Repl::with_config(engine, config);
+
+        repl.attributes.extend(input.attributes.into_iter());
+        repl.view_items.extend(input.view_items.into_iter());
+        repl.items.extend(input.items.into_iter());
+
+        // The prelude is compiled as an input would be, with the configured
+        // lints allowed. Its module is never marked finished, so it is kept.
+        let prog = repl.build_program(None, "");
+
+        match repl.engine.add_module_capture(prog) {
+            (Some(_), _) => {
+                repl.update_base();
+                Ok(repl)
+            }
+            (None, msgs) => Err(msgs),
+        }
+    }
+
+    /// Constructs a new `Repl` with the given engine, applying all
+    /// settings in `config` other than `sysroot`.
+    fn with_config(mut engine: ExecutionEngine, config: ReplConfig) -> Repl {
+        engine.set_opt_level(config.opt_level);
+        engine.set_cfgs(config.cfgs);
+
+        let mut repl = Repl::with_engine(engine, config.libs);
+
+        repl.allowed_lints = config.allowed_lints;
+        repl.prompt = config.prompt;
+        repl.more_prompt = config.more_prompt;
+        repl.block_prompt = config.block_prompt;
+        repl.history_size = config.history_size;
+
+        repl
+    }
+
+    fn with_engine(engine: ExecutionEngine, libs: Vec<String>) -> Repl {
         Repl{
-            engine: ExecutionEngine::new(libs),
+            engine: engine,
+            libs: libs,
             attributes: Vec::new(),
             view_items: Vec::new(),
             items: Vec::new(),
+            bindings: Vec::new(),
+            statics: Vec::new(),
+            undo_stack: Vec::new(),
             read_block: false,
+            read_paste: false,
+            stmt_mode: None,
+            pin_next: false,
+            transient_attrs: Vec::new(),
+            prompt: DEFAULT_PROMPT.to_string(),
+            more_prompt: MORE_PROMPT.to_string(),
+            block_prompt: BLOCK_PROMPT.to_string(),
+            history_size: DEFAULT_HISTORY_SIZE,
+            free_modules: true,
+            timeout: None,
+            debug: false,
+            allowed_lints: ReplConfig::default().allowed_lints,
+            color: false,
+            reader: None,
+            cwd: os::getcwd().unwrap_or_else(|_| Path::new(".")),
+            symbol_suffix: format!("{:08x}", rand::random::<u32>()),
+            base: None,
+            base_count: 0,
+            run_count: 0,
+            panicked: false,
+        }
+    }
+
+    /// Sets whether modules are removed after execution.
+    ///
+    /// When enabled, a module is removed only if no thread remains running
+    /// which its code may have spawned.
+    pub fn set_free_modules(&mut self, free: bool) {
+        self.free_modules = free;
+    }
+
+    /// Sets the maximum time to wait for a program to run. If a program
+    /// runs longer, it is abandoned and continues to run in the background.
+    ///
+    /// The default is `None`, meaning programs run until they finish.
+    pub fn set_timeout(&mut self, timeout: Option<Duration>) {
+        self.timeout = timeout;
+    }
+
+    /// Sets the lints allowed in every program. Warnings for these lints
+    /// are not emitted.
+    ///
+    /// The default is `dead_code` and `unused_imports`, as items and imports
+    /// defined in the session are often unused by a given input.
+    pub fn set_allowed_lints(&mut self, lints: Vec<String>) {
+        self.allowed_lints = lints;
+    }
+
+    /// Sets the prompt displayed when reading a new input.
+    pub fn set_prompt(&mut self, prompt: String) {
+        self.prompt = prompt;
+    }
+
+    /// Sets the prompt displayed when further input is being read.
+    pub fn set_more_prompt(&mut self, prompt: String) {
+        self.more_prompt = prompt;
+    }
+
+    /// Sets the prompt displayed when reading input for a `.block` command.
+    pub fn set_block_prompt(&mut self, prompt: String) {
+        self.block_prompt = prompt;
+    }
+
+    /// Returns the names of all items defined in the session, other than
+    /// `impl` blocks, all names imported by `use` and `extern crate`,
+    /// and all names bound by `let` and `.let`.
+    /// Names are sorted and contain no duplicates.
+    pub fn defined_names(&self) -> Vec<String> {
+        let mut names = Vec::new();
+
+        for (defs, _) in self.definitions().into_iter() {
+            names.extend(defs.into_iter());
+        }
+
+        names.extend(self.statics.iter().map(|s| s.name.clone()));
+
+        names.sort();
+        names.dedup();
+        names
+    }
+
+    /// Returns the source of each import, item, and binding in the session,
+    /// in that order, with the names it defines.
+    /// `impl` blocks define no names.
+    fn definitions(&self) -> Vec<(Vec<String>, &str)> {
+        let mut defs = Vec::new();
+
+        for &(_, ref src) in self.view_items.iter() {
+            defs.push((view_item_names(src.as_slice()), src.as_slice()));
+        }
+
+        for item in self.items.iter() {
+            let names = match item.kind {
+                ItemKind::Impl | ItemKind::TraitImpl => Vec::new(),
+                _ => item.name.clone().into_iter().collect(),
+            };
+
+            defs.push((names, item.source.as_slice()));
+        }
+
+        for b in self.bindings.iter() {
+            defs.push((b.names.clone(), b.source.as_slice()));
+        }
+
+        defs
+    }
+
+    /// Evaluates a single round of input, printing the result to `stdout`.
+    ///
+    /// Incomplete input, e.g. input containing unclosed delimiters,
+    /// is not run and no error is printed; the caller may read more input
+    /// and try again.
+    pub fn eval(&mut self, input: &str) -> EvalResult {
+        match parse_program(input, true, None) {
+            Program(i) => match self.run_input(i, ExprMode::Print, false) {
+                Ok(_) => EvalResult::Success,
+                Err(()) => EvalResult::CompileError,
+            },
+            More => EvalResult::Incomplete,
+            _ => EvalResult::ParseError,
+        }
+    }
+
+    /// Returns the type of the given expression, as it would be evaluated
+    /// in the current session, or `None` if the type could not be determined.
+    ///
+    /// The expression is not run and compiler diagnostics are not printed.
+    pub fn type_of(&self, expr: &str) -> Option<String> {
+        let expr = expr.trim();
+
+        if is_ident(expr) && self.has_binding(expr) {
+            return self.binding_type(expr);
+        }
+
+        if let Some(s) = self.statics.iter().find(|s| s.name == expr) {
+            return Some(s.ty.clone());
+        }
+
+        let name = self.internal_name("_rusti_type");
+        let prog = self.build_fn_program(None, name.as_slice(),
+            format!("{{ {} }};", expr).as_slice());
+
+        self.expr_type(name.as_slice(), prog, true)
+    }
+
+    /// Checks whether the given input would compile in the current session,
+    /// through type checking, without running it or adding to the session.
+    ///
+    /// Returns compiler diagnostics if the input fails to parse or compile.
+    pub fn check(&self, input: &str) -> Result<(), String> {
+        let name = self.internal_name("_rusti_check");
+
+        let prog = match parse_program_quiet(input, None) {
+            Program(mut input) => {
+                if input.last_expr {
+                    let stmt = input.statements.last_mut().unwrap();
+                    *stmt = format!("{{ ({}) }};", stmt);
+                }
+
+                self.build_fn_program(Some(&input), name.as_slice(),
+                    input.statements.connect("\n").as_slice())
+            }
+            // Input which fails to parse is given to the compiler as is,
+            // so that it may produce diagnostics.
+            _ => self.build_program(None,
+                format!("fn {}() {{\n{}\n}}", name, input).as_slice()),
+        };
+
+        match self.engine.with_analysis_capture(prog, |_| ()) {
+            (Ok(()), _) => Ok(()),
+            (Err(AnalysisError::CompileError), msgs) => Err(msgs),
+            (Err(AnalysisError::Panicked(msg)), _) => Err(msg),
+        }
+    }
+
+    /// Evaluates each of a series of inputs in order, as by `eval`.
+    ///
+    /// Evaluation stops at the first input which is incomplete or fails to
+    /// parse or compile, and the index of that input is returned.
+    /// State defined by earlier inputs is kept; the failed input adds none.
+    pub fn eval_all(&mut self, inputs: &[&str]) -> Result<(), uint> {
+        for (i, input) in inputs.iter().enumerate() {
+            if self.eval(*input) != EvalResult::Success {
+                return Err(i);
+            }
         }
+
+        Ok(())
     }
 
     /// Evaluates a single round of input, printing the result to `stdout`.
-    pub fn eval(&mut self, input: &str) {
+    /// Any errors, including errors for incomplete input, are printed.
+    pub fn eval_print(&mut self, input: &str) {
         match parse_program(input, false, None) {
-            Program(i) => self.handle_input(i),
+            Program(i) => { self.handle_input(i); }
             _ => (),
         }
     }
 
+    /// Evaluates a single round of input, returning everything printed to
+    /// `stdout` rather than writing it to the process `stdout`.
+    ///
+    /// Output includes the value of the final expression and any messages
+    /// printed by rusti, such as panic messages. Compiler diagnostics are
+    /// still written to `stderr`.
+    pub fn eval_capture(&mut self, input: &str) -> String {
+        let (tx, rx) = channel();
+
+        {
+            // Restoring the previous `stdout` drops the `ChanWriter`,
+            // so reading will stop at the end of the captured output.
+            let _guard = StdoutGuard{
+                prev: set_stdout(box ChanWriter::new(tx)),
+            };
+
+            self.eval_print(input);
+        }
+
+        ChanReader::new(rx).read_to_string().unwrap_or_else(|_| String::new())
+    }
+
+    /// Evaluates a single round of input. Rather than printing the value of
+    /// the final expression, the value is returned.
+    ///
+    /// The type of the value must be `'static` and `Send`.
+    /// Returns `None` if there is no final expression or if the input
+    /// fails to compile.
+    pub fn eval_value(&mut self, input: &str) -> Option<Box<Any + Send>> {
+        match parse_program(input, false, None) {
+            Program(i) => self.run_input(i, ExprMode::Return, false).unwrap_or(None),
+            _ => None,
+        }
+    }
+
     /// Runs the REPL interactively.
     pub fn run(&mut self) {
         let mut more = false;
-        let mut input = InputReader::new();
+        let mut reader = InputReader::new();
+
+        reader.set_interrupt_prompt(self.prompt.as_slice());
+        reader.set_history_size(self.history_size);
+
+        // The reader is held by the `Repl` so that commands may access history
+        self.reader = Some(reader);
+        self.color = stdout_raw().isatty() && os::getenv("NO_COLOR").is_none();
 
         loop {
-            let res = if self.read_block {
-                self.read_block = false;
-                input.read_block_input(BLOCK_PROMPT)
-            } else {
-                input.read_input(if more { MORE_PROMPT } else { DEFAULT_PROMPT })
+            let names = self.defined_names();
+
+            let res = {
+                let input = self.reader.as_mut().unwrap();
+
+                input.set_completions(command_names(), names);
+
+                if self.read_block {
+                    self.read_block = false;
+                    input.read_block_input(self.block_prompt.as_slice())
+                } else if self.read_paste {
+                    self.read_paste = false;
+                    input.read_paste_input()
+                } else {
+                    input.read_input(if more {
+                        self.more_prompt.as_slice()
+                    } else {
+                        self.prompt.as_slice()
+                    })
+                }
             };
 
             match res {
@@ -119,7 +793,8 @@ impl Repl {
                     more = false;
                     self.handle_input(input);
                 },
-                Empty => (),
+                // Input may have been discarded by an interrupt
+                Empty => { more = false; },
                 More => { more = true; },
                 Eof => {
                     if stdin_raw().isatty() {
@@ -129,12 +804,14 @@ impl Repl {
                 }
                 InputError(err) => {
                     if let Some(err) = err {
-                        println!("{}", err);
+                        println!("{}", self.paint(ERROR_COLOR, err.as_slice()));
                     }
                     more = false;
                 },
             };
         }
+
+        self.reader = None;
     }
 
     /// Runs a single `rusti` command.
@@ -146,9 +823,22 @@ impl Repl {
         }
     }
 
+    /// Runs a single round of input given as a string, as by the `-e` option,
+    /// printing the value of the final expression.
+    /// Returns `true` if it was compiled successfully and ran without panicking.
+    pub fn run_code(&mut self, code: &str) -> bool {
+        match parse_program(code, false, None) {
+            Program(input) => self.handle_input(input) && !self.panicked,
+            _ => false,
+        }
+    }
+
     /// Runs rusti input from the named file.
-    /// Returns `true` if it was compiled successfully.
+    /// Returns `false` if the file could not be read to the end;
+    /// inputs which fail to compile do not stop it.
     pub fn run_file(&mut self, path: Path) -> bool {
+        let path = self.cwd.join(path);
+
         let f = match File::open(&path) {
             Ok(f) => f,
             Err(e) => {
@@ -157,19 +847,29 @@ impl Repl {
             }
         };
 
-        let mut input = FileReader::new(f);
+        self.run_reader(FileReader::new(f))
+    }
+
+    /// Runs rusti input read from `stdin` until end-of-file, as though
+    /// it were a file. Returns `false` if it could not be read to the end.
+    pub fn run_stdin(&mut self) -> bool {
+        self.run_reader(FileReader::with_reader(stdin_raw(), Some("<stdin>".to_string())))
+    }
 
+    /// Runs rusti input from a non-interactive source.
+    /// Returns `false` if reading stopped before end-of-file.
+    fn run_reader<R: Reader>(&mut self, mut input: FileReader<R>) -> bool {
         loop {
-            if self.read_block {
-                println!("{}: `.block` command is not necessary when running a file",
-                    os::args()[0]);
+            if self.read_block || self.read_paste {
+                println!("{}: `.{}` command is not necessary when running a file",
+                    os::args()[0], if self.read_block { "block" } else { "paste" });
                 return false;
             }
 
             let input = input.read_input();
 
             match input {
-                Program(input) => self.handle_input(input),
+                Program(input) => { self.handle_input(input); }
                 Command(name, args) => self.handle_command(name, args),
                 InputError(Some(e)) => {
                     println!("{}: {}", os::args()[0], e);
@@ -184,24 +884,58 @@ impl Repl {
         true
     }
 
+    /// Removes all persistent attributes, view items, and items
+    /// and replaces the execution engine with a new one.
+    pub fn reset(&mut self) {
+        let opt_level = self.engine.opt_level();
+
+        self.engine = ExecutionEngine::new_with_sysroot(self.libs.clone(),
+            self.engine.sysroot().clone());
+        self.engine.set_opt_level(opt_level);
+        self.attributes.clear();
+        self.view_items.clear();
+        self.items.clear();
+        // The base module was held in the old execution engine
+        self.base = None;
+        self.bindings.clear();
+        // Values of `.let` bindings were held in the old execution engine
+        self.statics.clear();
+        self.undo_stack.clear();
+        self.read_block = false;
+        self.read_paste = false;
+        self.stmt_mode = None;
+        self.pin_next = false;
+        self.transient_attrs.clear();
+    }
+
     /// Build a program text containing all persistent items seen so far and,
     /// optionally, those from an `Input` instance. The `statements` field of
     /// `input` will be ignored.
     fn build_program(&self, input: Option<&Input>, program: &str) -> String {
+        self.build_program_with(input, &[], program)
+    }
+
+    /// Builds a program text as `build_program` does, adding the given
+    /// crate attributes after all persistent attributes.
+    fn build_program_with(&self, input: Option<&Input>, extra_attrs: &[String],
+            program: &str) -> String {
         let (attrs, vitems, items) = if let Some(input) = input {
             let attrs = self.attributes.iter().map(|s| s.as_slice())
                 .chain(input.attributes.iter().map(|s| s.as_slice()))
                 .collect::<Vec<_>>();
 
-            let mut vitems = self.view_items.iter().map(|&(a, ref b)| (a, b.as_slice()))
-                .chain(input.view_items.iter().map(|&(a, ref b)| (a, b.as_slice())))
-                .collect::<Vec<_>>();
-
-            // Sort `extern crate` before `use`
-            vitems.sort_by(|&(a, _), &(b, _)| a.cmp(&b));
+            let vitems = sort_view_items(self.view_items.iter()
+                .chain(input.view_items.iter())
+                .map(|&(a, ref b)| (a, b.as_slice())).collect());
 
-            let items = self.items.iter().map(|s| s.as_slice())
-                .chain(input.items.iter().map(|s| s.as_slice()))
+            // Items redefined by the input are replaced. Functions in the base
+            // may depend on a redefined item, so none are called through it.
+            let redefined = self.items.iter()
+                .any(|old| input.items.iter().any(|new| redefines(new, old)));
+            let items = self.items.iter()
+                .filter(|old| !input.items.iter().any(|new| redefines(new, *old)))
+                .map(|i| if redefined { i.source.clone() } else { self.item_source(i) })
+                .chain(input.items.iter().map(|i| i.source.clone()))
                 .collect::<Vec<_>>();
 
             (attrs, vitems, items)
@@ -209,26 +943,33 @@ impl Repl {
             let attrs = self.attributes.iter().map(|s| s.as_slice())
                 .collect::<Vec<_>>();
 
-            let mut vitems = self.view_items.iter().map(|&(a, ref b)| (a, b.as_slice()))
-                .collect::<Vec<_>>();
-
-            // Sort `extern crate` before `use`
-            vitems.sort_by(|&(a, _), &(b, _)| a.cmp(&b));
+            let vitems = sort_view_items(self.view_items.iter()
+                .map(|&(a, ref b)| (a, b.as_slice())).collect());
 
-            let items = self.items.iter().map(|s| s.as_slice())
+            let items = self.items.iter().map(|i| self.item_source(i))
                 .collect::<Vec<_>>();
 
             (attrs, vitems, items)
         };
 
-        let attrs = attrs.connect("\n");
+        let allow = if self.allowed_lints.is_empty() {
+            None
+        } else {
+            Some(format!("#![allow({})]", self.allowed_lints.connect(", ")))
+        };
+
+        // Crate attributes, such as `#![feature(...)]`, must precede all items,
+        // so they are emitted together as the first lines of the program.
+        let attrs = allow.iter().map(|s| s.as_slice())
+            .chain(attrs.into_iter().map(|s| s.trim()))
+            .chain(extra_attrs.iter().map(|s| s.as_slice()))
+            .collect::<Vec<_>>().connect("\n");
         let vitems = vitems.iter().map(|&(_, s)| s)
             .collect::<Vec<_>>().connect("\n");
         let items = items.connect("\n");
 
         format!(
-r#"#![allow(dead_code, unused_imports)]
-{attrs}
+r#"{attrs}
 {vitems}
 {items}
 {program}
@@ -239,114 +980,2374 @@ r#"#![allow(dead_code, unused_imports)]
         , program = program)
     }
 
-    /// Runs a single command input.
-    fn handle_command(&mut self, cmd: String, args: Option<String>) {
-        match lookup_command(cmd.as_slice()) {
-            Some("block") => {
-                if args.is_some() {
-                    println!("command `block` takes no arguments");
-                } else {
-                    self.read_block = true;
-                }
-            },
-            Some("type") => {
-                if let Some(args) = args {
-                    self.type_command(args);
-                } else {
-                    println!("command `type` expects an expression");
-                }
-            },
-            _ => println!("unrecognized command `{}`", cmd),
+    /// Builds a program, as `build_program` does, which defines the function
+    /// `fn_name` to run the given statements after all persistent `let`
+    /// declarations. The function is compiled only to be analyzed.
+    fn build_fn_program(&self, input: Option<&Input>, fn_name: &str, stmts: &str) -> String {
+        self.build_program(input, format!(
+r#"
+#[allow(unused_variables, unused_mut)]
+fn {name}() {{
+{body}
+}}
+"#
+        , name = fn_name
+        , body = self.fn_body(stmts)
+        ).as_slice())
+    }
+
+    /// Returns the body of a function which runs the given statements
+    /// after all persistent `let` declarations.
+    fn fn_body(&self, stmts: &str) -> String {
+        let mut body = self.binding_statements();
+        body.push_str(stmts);
+        body
+    }
+
+    /// Returns the source code compiled for a session item. A function
+    /// compiled into the base is replaced by one of the same name
+    /// and signature which calls into the base.
+    fn item_source(&self, item: &Item) -> String {
+        let sym = self.base.as_ref().and_then(|base| base.symbols.iter()
+            .find(|&&(ref src, _)| *src == item.source).map(|&(_, ref sym)| sym));
+
+        match (sym, &item.fn_types, &item.name) {
+            (Some(sym), &Some(ref types), &Some(ref name)) => {
+                let params = types.inputs.iter().enumerate()
+                    .map(|(i, ty)| format!("_{}: {}", i, ty))
+                    .collect::<Vec<_>>().connect(", ");
+                let args = range(0, types.inputs.len())
+                    .map(|i| format!("_{}", i)).collect::<Vec<_>>().connect(", ");
+
+                format!(
+r#"fn {name}({params}) -> {output} {{
+    #[allow(warnings)]
+    extern "Rust" {{
+        #[link_name = "{sym}"]
+        fn {name}({params}) -> {output};
+    }}
+    unsafe {{ {name}({args}) }}
+}}"#
+                , name = name
+                , params = params
+                , output = types.output
+                , sym = sym
+                , args = args)
+            }
+            _ => item.source.clone()
         }
     }
 
-    /// Runs a single program input.
-    fn handle_input(&mut self, mut input: Input) {
-        let name = "_rusti_run";
+    /// Compiles session functions which are not yet compiled into a new
+    /// module of the base, which is kept along with earlier modules.
+    /// If an item compiled along with the base has since been removed or
+    /// redefined, compiled functions may depend on it, so the base is
+    /// released and all session functions are compiled again.
+    ///
+    /// Functions reading or writing a `static` would see a different copy
+    /// of the `static` than code compiled for each input,
+    /// so functions are not compiled into a base module while
+    /// the session contains any `static` items.
+    fn update_base(&mut self) {
+        let stale = match self.base {
+            Some(ref base) => base.items.iter()
+                .any(|src| !self.items.iter().any(|i| i.source == *src)),
+            None => false,
+        };
+        let has_statics = self.items.iter().any(|i| i.kind == ItemKind::Static);
 
-        if input.last_expr && !input.statements.is_empty() {
-            let stmt = input.statements.last_mut().unwrap();
-            *stmt = format!(r#"println!("{{}}", {{ {} }});"#, stmt);
+        if stale || has_statics {
+            self.release_base();
         }
 
-        let stmts = input.statements.connect("\n");
+        if has_statics {
+            return;
+        }
 
-        let prog = self.build_program(Some(&input),
-            format!(
-r#"
-#[no_mangle]
-pub fn {name}() {{
-    let _ = unsafe {{ std::rt::unwind::try(_rusti_inner) }};
-}}
+        let (symbols, shims) = {
+            let fns = self.items.iter()
+                .filter(|i| i.fn_types.is_some() && i.name.is_some())
+                .filter(|i| !self.base.as_ref().map_or(false,
+                    |base| base.symbols.iter().any(|&(ref src, _)| *src == i.source)))
+                .collect::<Vec<_>>();
 
-fn _rusti_inner() {{
-{stmts}
-}}
-"#
-            , name = name
-            , stmts = stmts
-            ).as_slice()
-        );
+            if fns.is_empty() {
+                return;
+            }
 
-        if let Some(_) = self.engine.add_module(prog) {
-            let fp = self.engine.get_function(name).unwrap();
-            let f: fn() = unsafe { transmute(fp) };
+            self.base_count += 1;
 
-            f();
+            let mut symbols = Vec::new();
+            let mut shims = String::new();
 
-            // NOTE: The module cannot be removed after it is run because tasks
-            // may still be running in the module code. This means that rusti's
-            // memory footprint will only grow over time.
-            // Hopefully, this will not be noticeable in normal use.
+            // Each function is exported under a unique symbol through a shim,
+            // leaving the function's own definition unchanged.
+            for (i, item) in fns.iter().enumerate() {
+                let types = item.fn_types.as_ref().unwrap();
+                let sym = self.internal_name(format!("_rusti_base{}_{}",
+                    self.base_count, i).as_slice());
 
-            // Successful compile means we can add the new items to every program
-            self.attributes.extend(input.attributes.into_iter());
-            self.view_items.extend(input.view_items.into_iter());
-            self.items.extend(input.items.into_iter());
+                let params = types.inputs.iter().enumerate()
+                    .map(|(i, ty)| format!("_{}: {}", i, ty))
+                    .collect::<Vec<_>>().connect(", ");
+                let args = range(0, types.inputs.len())
+                    .map(|i| format!("_{}", i)).collect::<Vec<_>>().connect(", ");
+
+                shims.push_str(format!(
+                    "#[export_name = \"{sym}\"]\npub fn {sym}({params}) -> {output} \
+                        {{ {name}({args}) }}\n"
+                    , sym = sym
+                    , params = params
+                    , output = types.output
+                    , name = item.name.as_ref().unwrap()
+                    , args = args).as_slice());
+
+                symbols.push((item.source.clone(), sym));
+            }
+
+            (symbols, shims)
+        };
+
+        // Functions already in the base are called through it,
+        // so only the new functions are compiled.
+        let prog = self.build_program(None, shims.as_slice());
+
+        // Diagnostics were shown when the items were compiled for input
+        let llmod = match self.engine.add_module_capture(prog) {
+            (Some(llmod), _) => llmod,
+            (None, _) => return,
+        };
+
+        self.engine.set_pinned(llmod, true);
+
+        let items = self.items.iter().map(|i| i.source.clone()).collect();
+
+        match self.base {
+            Some(ref mut base) => {
+                base.modules.push(llmod);
+                base.symbols.extend(symbols.into_iter());
+                base.items = items;
+            }
+            None => self.base = Some(BaseModule{
+                modules: vec![llmod],
+                symbols: symbols,
+                items: items,
+                kept: false,
+            }),
         }
     }
 
-    fn expr_type(&self, fn_name: &str, prog: String) -> Option<String> {
-        let fn_name = fn_name.to_string();
+    /// Releases the base, so that session functions are compiled with
+    /// each input until they are compiled into a new base.
+    /// Its modules are removed, unless code which may call into them
+    /// has been kept.
+    fn release_base(&mut self) {
+        let base = match self.base.take() {
+            Some(base) => base,
+            None => return,
+        };
 
-        self.engine.with_analysis(prog, move |analysis| {
-            let mut v = ExprType{
-                fn_name: fn_name,
-                result: None,
-                ty_cx: &analysis.ty_cx,
-            };
+        for &llmod in base.modules.iter() {
+            self.engine.set_pinned(llmod, false);
 
-            visit::walk_crate(&mut v, analysis.ty_cx.map.krate());
+            if !base.kept {
+                self.engine.set_finished(llmod);
 
-            if let Some(ty) = v.result {
-                ty
-            } else {
-                panic!("no type found");
+                if self.free_modules {
+                    if let Err(e) = self.engine.remove_module(llmod) {
+                        debug!("failed to remove module: {}", e);
+                    }
+                }
             }
-        })
+        }
     }
 
-    fn type_command(&mut self, expr: String) {
-        let name = "_rusti_type";
-        let prog = self.build_program(None, format!(
-r#"
-fn {name}() {{
-{expr}
-}}
-"#
-        , name = name
-        , expr = format!("{{ {} }};", expr)
-        ).as_slice());
+    /// Returns source code for all persistent attributes, view items,
+    /// and items.
+    fn session_source(&self) -> String {
+        let vitems = sort_view_items(self.view_items.iter()
+            .map(|&(a, ref b)| (a, b.as_slice())).collect());
 
-        if let Some(t) = self.expr_type(name, prog) {
-            println!("{} = {}", expr, t);
+        let mut src = String::new();
+
+        for attr in self.attributes.iter() {
+            src.push_str(attr.as_slice());
+            src.push('\n');
+        }
+
+        for &(_, vitem) in vitems.iter() {
+            src.push_str(vitem);
+            src.push('\n');
+        }
+
+        for item in self.items.iter() {
+            src.push_str(item.source.as_slice());
+            src.push('\n');
         }
+
+        src
     }
-}
 
-struct ExprType<'a, 'tcx: 'a> {
+    /// Replaces all persistent attributes, view items, and items with those
+    /// from the given input. Returns `false` if the input fails to compile,
+    /// in which case the session is unchanged.
+    fn replace_session(&mut self, input: Input) -> bool {
+        let attributes = replace(&mut self.attributes, Vec::new());
+        let view_items = replace(&mut self.view_items, Vec::new());
+        let items = replace(&mut self.items, Vec::new());
+
+        if self.run_input(input, ExprMode::Print, false).is_ok() {
+            // Earlier states no longer correspond to session contents
+            self.undo_stack.clear();
+            true
+        } else {
+            self.attributes = attributes;
+            self.view_items = view_items;
+            self.items = items;
+            false
+        }
+    }
+
+    /// Runs a single command input.
+    fn handle_command(&mut self, cmd: String, args: Option<String>) {
+        // `.!<n>` and `.!!` re-run history entries, as in a shell
+        if cmd.starts_with("!") {
+            if args.is_some() {
+                println!("command `{}` takes no arguments", cmd);
+            } else {
+                self.rerun_command(cmd.slice_from(1));
+            }
+            return;
+        }
+
+        // A trailing `!` overrides a command's safety checks
+        let (name, force) = if cmd.ends_with("!") {
+            (cmd.slice_to(cmd.len() - 1), true)
+        } else {
+            (cmd.as_slice(), false)
+        };
+
+        let cmd_name = match lookup_command(name) {
+            CommandLookup::Found(cmd) => Some(cmd),
+            CommandLookup::Ambiguous(cmds) => {
+                print_ambiguous(name, cmds.as_slice());
+                return;
+            }
+            CommandLookup::NotFound => None,
+        };
+
+        match cmd_name {
+            Some("cd") => {
+                if let Some(args) = args {
+                    self.cd_command(args.as_slice());
+                } else {
+                    println!("command `cd` expects a directory");
+                }
+            },
+            Some("cfg") => self.cfg_command(args),
+            Some("debug") => self.debug_command(args),
+            Some("opt") => self.opt_command(args),
+            Some("print") => {
+                if args.is_some() {
+                    println!("command `print` takes no arguments");
+                } else {
+                    self.print_command();
+                }
+            },
+            Some("pwd") => {
+                if args.is_some() {
+                    println!("command `pwd` takes no arguments");
+                } else {
+                    println!("{}", self.cwd.display());
+                }
+            },
+            Some("reload") => {
+                if args.is_some() {
+                    println!("command `reload` takes no arguments");
+                } else {
+                    self.reload_command();
+                }
+            },
+            Some("reset") => self.reset_command(args),
+            Some("run") => {
+                if let Some(args) = args {
+                    self.run_fn_command(args.as_slice());
+                } else {
+                    println!("command `run` expects a function name");
+                }
+            },
+            Some("save") => {
+                if let Some(args) = args {
+                    self.save_command(args.as_slice(), force);
+                } else {
+                    println!("command `save` expects a filename");
+                }
+            },
+            Some(cmd) if force => println!("command `{}` does not accept `!`", cmd),
+            Some("allow") => {
+                if let Some(args) = args {
+                    self.lint_level_command("allow", args.as_slice());
+                } else {
+                    println!("command `allow` expects a lint name");
+                }
+            },
+            Some("deny") => {
+                if let Some(args) = args {
+                    self.lint_level_command("deny", args.as_slice());
+                } else {
+                    println!("command `deny` expects a lint name");
+                }
+            },
+            Some("ast") => {
+                if let Some(args) = args {
+                    self.ast_command(args.as_slice());
+                } else {
+                    println!("command `ast` expects Rust code");
+                }
+            },
+            Some("block") => {
+                if args.is_some() {
+                    println!("command `block` takes no arguments");
+                } else {
+                    self.read_block = true;
+                }
+            },
+            Some("paste") => {
+                if args.is_some() {
+                    println!("command `paste` takes no arguments");
+                } else {
+                    println!("// paste code, then enter `.end` on its own line");
+                    self.read_paste = true;
+                }
+            },
+            Some("doc") => {
+                if let Some(args) = args {
+                    self.doc_command(args.as_slice().trim());
+                } else {
+                    println!("command `doc` expects an item path");
+                }
+            },
+            Some("let") => {
+                if let Some(args) = args {
+                    self.let_command(args.as_slice());
+                } else {
+                    println!("command `let` expects `<name> = <expr>`");
+                }
+            },
+            Some("expr") => self.stmt_mode_command(StmtMode::Expr, args),
+            Some("pin") => self.pin_command(args),
+            Some("stmt") => self.stmt_mode_command(StmtMode::Stmt, args),
+            Some("search") => {
+                if let Some(args) = args {
+                    self.search_command(args.as_slice());
+                } else {
+                    println!("command `search` expects text to search for");
+                }
+            },
+            Some("crate") => {
+                if let Some(args) = args {
+                    self.crate_command(args);
+                } else {
+                    println!("command `crate` expects a directory");
+                }
+            },
+            Some("def") => {
+                if let Some(args) = args {
+                    self.def_command(args.as_slice());
+                } else {
+                    println!("command `def` expects a name");
+                }
+            },
+            Some("edit") => {
+                if args.is_some() {
+                    println!("command `edit` takes no arguments");
+                } else {
+                    self.edit_command();
+                }
+            },
+            Some("env") => {
+                if args.is_some() {
+                    println!("command `env` takes no arguments");
+                } else {
+                    self.env_command();
+                }
+            },
+            Some("gc") => {
+                if args.is_some() {
+                    println!("command `gc` takes no arguments");
+                } else {
+                    self.gc_command();
+                }
+            },
+            Some("get") => {
+                if let Some(args) = args {
+                    self.get_command(args.as_slice().trim());
+                } else {
+                    println!("command `get` expects the name of a static");
+                }
+            },
+            Some("help") => self.help_command(args),
+            Some("history") => self.history_command(args),
+            Some("import") => {
+                if let Some(args) = args {
+                    self.import_command(args.as_slice());
+                } else {
+                    println!("command `import` expects a path");
+                }
+            },
+            Some("lints") => self.lints_command(args),
+            Some("rm-import") => {
+                if let Some(args) = args {
+                    self.rm_import_command(args.as_slice().trim());
+                } else {
+                    println!("command `rm-import` expects a name or path");
+                }
+            },
+            Some("load") => {
+                if let Some(args) = args {
+                    self.load_command(args.as_slice());
+                } else {
+                    println!("command `load` expects a filename");
+                }
+            },
+            Some("load-expr") => {
+                if let Some(args) = args {
+                    self.load_expr_command(args.as_slice());
+                } else {
+                    println!("command `load-expr` expects a filename");
+                }
+            },
+            Some("bench") => {
+                if let Some(args) = args {
+                    self.bench_command(args.as_slice());
+                } else {
+                    println!("command `bench` expects an expression");
+                }
+            },
+            Some("time") => {
+                if let Some(args) = args {
+                    self.time_command(args.as_slice());
+                } else {
+                    println!("command `time` expects code to run");
+                }
+            },
+            Some("timeout") => self.timeout_command(args),
+            Some("trace") => self.trace_command(args),
+            Some("type") => {
+                if let Some(args) = args {
+                    self.type_command(args);
+                } else {
+                    println!("command `type` expects an expression");
+                }
+            },
+            Some("undo") => {
+                if args.is_some() {
+                    println!("command `undo` takes no arguments");
+                } else if !self.undo() {
+                    println!("nothing to undo");
+                }
+            },
+            Some("version") => {
+                if args.is_some() {
+                    println!("command `version` takes no arguments");
+                } else {
+                    self.version_command();
+                }
+            },
+            Some("which") => {
+                if let Some(args) = args {
+                    self.which_command(args.as_slice());
+                } else {
+                    println!("command `which` expects a symbol name");
+                }
+            },
+            _ => println!("unrecognized command `{}`", cmd),
+        }
+
+        self.print_traces();
+    }
+
+    /// Runs a single program input.
+    /// Returns `true` if it was compiled successfully.
+    fn handle_input(&mut self, mut input: Input) -> bool {
+        if let Some(mode) = self.stmt_mode.take() {
+            if !force_stmt_mode(&mut input, mode) {
+                return false;
+            }
+        }
+
+        let ok = self.run_input(input, ExprMode::Print, false).is_ok();
+        self.print_traces();
+        ok
+    }
+
+    /// Compiles and runs a single program input. The value of the final
+    /// expression, if there is one, is handled according to `mode`.
+    ///
+    /// If `timed` is true, time spent compiling and running the program
+    /// is printed after it has run.
+    ///
+    /// Returns `Err(())` if the program could not be compiled. Otherwise,
+    /// returns the value of the final expression if `mode` is `Return`
+    /// and the program ran without panicking.
+    fn run_input(&mut self, mut input: Input, mode: ExprMode, timed: bool)
+            -> Result<Option<Box<Any + Send>>, ()> {
+        let pin = replace(&mut self.pin_next, false);
+        let transient_attrs = replace(&mut self.transient_attrs, Vec::new());
+        let name = self.internal_name("_rusti_run");
+        let inner = self.internal_name("_rusti_inner");
+
+        self.run_count += 1;
+        let entry = self.entry_name();
+
+        if let Some(item) = self.items.iter().chain(input.items.iter())
+                .find(|i| i.name == Some(name.clone()) || i.name == Some(inner.clone())) {
+            println!("item `{}` conflicts with a name used by rusti",
+                item.name.as_ref().unwrap());
+            return Err(());
+        }
+
+        // Repeated view items are dropped; a duplicate `extern crate`
+        // would otherwise cause an error.
+        let mut seen = self.view_items.iter()
+            .map(|&(_, ref s)| normalize_source(s.as_slice())).collect::<Vec<_>>();
+
+        input.view_items.retain(|&(_, ref s)| {
+            let norm = normalize_source(s.as_slice());
+
+            if seen.contains(&norm) {
+                false
+            } else {
+                seen.push(norm);
+                true
+            }
+        });
+
+        let mut tail = "None".to_string();
+        // Source of the final expression, if it is printed
+        let mut print_expr = None;
+
+        if input.last_expr && !input.statements.is_empty() {
+            let stmt = input.statements.pop().unwrap();
+
+            // Parentheses ensure that block and closure expressions are
+            // parsed in expression position, not as statements in the block.
+            match mode {
+                ExprMode::Print => print_expr = Some(stmt),
+                ExprMode::Return => {
+                    tail = format!("Some((box {{ ({}) }}) as Box<::std::any::Any + Send>)", stmt);
+                }
+            }
+        }
+
+        let mut prog = String::new();
+        // Type of the final expression, if it is printed in place of the value
+        let mut expr_type = None;
+
+        let mut res = (None, Vec::new());
+        let compile_time = Duration::span(|| {
+            let mut stmts = input.statements.connect("\n");
+
+            if let Some(ref expr) = print_expr {
+                // Escape sequences are written into the generated program,
+                // which prints the value itself.
+                let (start, end) = if self.color {
+                    (RESULT_COLOR.escape_default(), RESET_COLOR.escape_default())
+                } else {
+                    (String::new(), String::new())
+                };
+
+                // A value which cannot be formatted is evaluated
+                // and its type is printed instead.
+                let print = match self.print_format(&input, expr.as_slice()) {
+                    PrintFormat::Format(fmt) => format!(r#"println!("{}{}{}", {{ ({}) }});"#,
+                        start, fmt, end, expr),
+                    PrintFormat::Type(ty) => {
+                        expr_type = Some(ty);
+                        format!("{{ ({}) }};", expr)
+                    }
+                };
+
+                stmts.push('\n');
+                stmts.push_str(print.as_slice());
+            }
+
+            let stmts = self.fn_body(format!("{}\n{}", stmts, tail).as_slice());
+
+            prog = self.build_program_with(Some(&input), transient_attrs.as_slice(),
+                run_wrapper(entry.as_slice(), name.as_slice(), inner.as_slice(),
+                    stmts.as_slice()).as_slice());
+
+            res = self.engine.add_module_codes(prog.as_slice());
+        });
+        let (res, codes) = res;
+
+        self.print_traces();
+
+        if res.is_none() && self.debug {
+            for (i, line) in prog.as_slice().lines().enumerate() {
+                println!("{:>4} {}", i + 1, line);
+            }
+        }
+
+        if res.is_none() && !self.view_items.is_empty() && is_import_conflict(codes.as_slice()) {
+            println!("this import conflicts with a previously imported name; try `.rm-import`");
+        }
+
+        if let Some(llmod) = res {
+            let f: fn(&mut Option<Box<Any + Send>>) -> Option<String> =
+                match unsafe { self.engine.get_fn(entry.as_slice()) } {
+                    Some(f) => f,
+                    None => {
+                        println!("function `{}` not found in compiled program", entry);
+                        return Err(());
+                    }
+                };
+
+            let mut value = None;
+            let mut msg = None;
+            let mut timed_out = false;
+
+            let timeout = self.timeout;
+            let threads = thread_count();
+
+            let run_time = Duration::span(|| match timeout {
+                Some(limit) => match run_with_timeout(f, limit) {
+                    Some((v, m)) => { value = v; msg = m; }
+                    None => timed_out = true,
+                },
+                None => msg = f(&mut value),
+            });
+
+            let panicked = match msg {
+                _ if timed_out => {
+                    println!("{}", self.paint(ERROR_COLOR, format!(
+                        "{}: evaluation timed out", os::args()[0]).as_slice()));
+                    true
+                }
+                Some(msg) => {
+                    println!("{}", self.paint(ERROR_COLOR, format!(
+                        "{}: evaluation panicked: {}", os::args()[0], msg).as_slice()));
+                    true
+                }
+                None => {
+                    if let Some(ty) = expr_type {
+                        println!("{}", self.paint(RESULT_COLOR,
+                            format!("<{}>", ty).as_slice()));
+                    }
+                    false
+                }
+            };
+
+            if timed {
+                println!("compile: {}, run: {}",
+                    format_duration(compile_time), format_duration(run_time));
+            }
+
+            self.panicked = panicked;
+
+            let spawns = match (threads, thread_count()) {
+                (Some(before), Some(after)) => after > before,
+                _ => true,
+            };
+
+            // Code kept in the module may call into the base,
+            // as may a value stored in a `.let` binding.
+            if pin || spawns || timed_out || value.is_some() || !self.statics.is_empty() {
+                if let Some(ref mut base) = self.base {
+                    base.kept = true;
+                }
+            }
+
+            // NOTE: A module cannot be removed after it is run if threads
+            // may still be running in the module code. In that case, the module
+            // is kept and rusti's memory footprint will grow.
+            // Threads are counted before and after the program is run,
+            // so a helper thread first started by the standard library
+            // during the run, as for a timer, also keeps the module.
+            // A returned value also refers to its type's vtable within
+            // the module, so the module must outlive the value.
+            // Code which timed out is still running.
+            // A finished module that is kept may later be removed by `.gc`,
+            // unless it is pinned.
+            if pin {
+                self.engine.set_pinned(llmod, true);
+            }
+
+            if !spawns && !timed_out && value.is_none() {
+                self.engine.set_finished(llmod);
+
+                if self.free_modules && !pin {
+                    if let Err(e) = self.engine.remove_module(llmod) {
+                        debug!("failed to remove module: {}", e);
+                    }
+                }
+            }
+
+            // A pasted program's `main` is an ordinary function here
+            if input.statements.is_empty() && input.items.iter()
+                    .any(|i| i.kind == ItemKind::Fn && i.name == Some("main".to_string())) {
+                println!("function `main` will not run automatically; \
+                    enter `main()` to run it");
+            }
+
+            let snapshot = self.snapshot();
+            let adds_bindings = !panicked && input.bindings.iter().any(|b| b.replayable);
+
+            if adds_bindings || !input.attributes.is_empty() ||
+                    !input.view_items.is_empty() || !input.items.is_empty() {
+                self.undo_stack.push(snapshot);
+            }
+
+            // Successful compile means we can add the new items to every program
+            self.attributes.extend(input.attributes.into_iter());
+            self.view_items.extend(input.view_items.into_iter());
+            self.items.retain(|old| !input.items.iter().any(|new| redefines(new, old)));
+
+            let adds_items = !input.items.is_empty();
+            self.items.extend(input.items.into_iter());
+
+            if adds_items {
+                self.update_base();
+            }
+
+            // Bindings are kept only if their initializers ran successfully
+            if !panicked {
+                for b in input.bindings.into_iter() {
+                    if b.replayable {
+                        self.add_binding(b);
+                    } else {
+                        println!("binding `{}` will not persist because its \
+                            initializer may have side effects", b.names.connect("`, `"));
+
+                        // Earlier values of the names would otherwise be seen
+                        let end = self.bindings.len();
+                        self.remove_shadowed(b.names.as_slice(), end);
+                    }
+                }
+            }
+
+            Ok(value)
+        } else {
+            Err(())
+        }
+    }
+
+    /// Returns the current persistent state.
+    fn snapshot(&self) -> Snapshot {
+        Snapshot{
+            attributes: self.attributes.len(),
+            view_items: self.view_items.len(),
+            items: self.items.clone(),
+            bindings: self.bindings.clone(),
+            statics: self.statics.clone(),
+        }
+    }
+
+    /// Removes everything added by the last input which added to the
+    /// persistent state. Returns `false` if there is no such input.
+    pub fn undo(&mut self) -> bool {
+        match self.undo_stack.pop() {
+            Some(snapshot) => {
+                self.attributes.truncate(snapshot.attributes);
+                self.view_items.truncate(snapshot.view_items);
+                self.items = snapshot.items;
+                self.bindings = snapshot.bindings;
+                self.statics = snapshot.statics;
+                self.update_base();
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Returns source for all persistent `let` declarations, to be placed
+    /// at the start of a function body.
+    fn binding_statements(&self) -> String {
+        let mut stmts = String::new();
+
+        // `.let` bindings are references to their `static mut` values,
+        // which are declared by symbol name
+        for s in self.statics.iter() {
+            stmts.push_str(format!(
+                "#[allow(warnings)]\nextern \"Rust\" {{ static mut {sym}: {ty}; }}\n\
+                 let {name}: &mut {ty} = unsafe {{ &mut {sym} }};\n",
+                name = s.name, ty = s.ty, sym = s.sym).as_slice());
+        }
+
+        for b in self.bindings.iter() {
+            stmts.push_str(b.source.as_slice());
+            stmts.push('\n');
+        }
+
+        stmts
+    }
+
+    /// Adds a binding to be evaluated in every later program.
+    /// An earlier binding is removed if all of its names are shadowed by the
+    /// new binding and none of its values are used by any later binding.
+    fn add_binding(&mut self, binding: Binding) {
+        let names = binding.names.clone();
+        let end = self.bindings.len();
+
+        self.bindings.push(binding);
+        self.remove_shadowed(names.as_slice(), end);
+    }
+
+    /// Removes each of the first `end` bindings if all of its names are
+    /// among `names` and none of its values are used by any later binding.
+    fn remove_shadowed(&mut self, names: &[String], mut end: uint) {
+        let mut i = 0;
+
+        while i < end {
+            let remove = {
+                let old = &self.bindings[i];
+
+                old.names.iter().all(|n| names.contains(n)) &&
+                    !self.bindings.slice_from(i + 1).iter()
+                        .any(|b| b.refs.iter().any(|r| old.names.contains(r)))
+            };
+
+            if remove {
+                self.bindings.remove(i);
+                end -= 1;
+            } else {
+                i += 1;
+            }
+        }
+    }
+
+    fn ast_command(&self, code: &str) {
+        let input = match parse_program(code, false, None) {
+            Program(input) => input,
+            _ => return
+        };
+
+        for attr in input.attributes.iter() {
+            println!("attribute: {}", attr);
+        }
+        for &(_, ref vitem) in input.view_items.iter() {
+            println!("view item: {}", vitem);
+        }
+        for item in input.items.iter() {
+            println!("item: {}", item.source);
+        }
+        for (i, stmt) in input.statements.iter().enumerate() {
+            if input.last_expr && i == input.statements.len() - 1 {
+                println!("expression: {}", stmt);
+            } else {
+                println!("statement: {}", stmt);
+            }
+        }
+
+        if let Some(nodes) = parse_ast(code) {
+            for node in nodes.iter() {
+                println!("\n{}", node);
+            }
+        }
+    }
+
+    fn bench_command(&mut self, args: &str) {
+        // A leading number is the iteration count
+        let (count, expr) = match args.find(|c: char| c.is_whitespace()) {
+            Some(i) => match args.slice_to(i).parse::<uint>() {
+                Some(n) => (n, args.slice_from(i).trim()),
+                None => (BENCH_ITERATIONS, args),
+            },
+            None => (BENCH_ITERATIONS, args),
+        };
+
+        if count == 0 {
+            println!("iteration count must be greater than zero");
+            return;
+        }
+
+        // The value is passed to a function which is never inlined,
+        // so that the optimizer cannot discard the work of the expression.
+        let code = format!(
+r#"{{
+    #[inline(never)]
+    fn {black_box}<T>(value: T) -> T {{ value }}
+
+    let {times} = range(0u, {count}).map(|_| ::std::time::Duration::span(|| {{
+        {black_box}({{ ({expr}) }});
+    }}).num_nanoseconds().unwrap_or(0)).collect::<Vec<i64>>();
+
+    println!("{{}} iterations; mean: {{}} ns, min: {{}} ns, max: {{}} ns", {count}u,
+        {times}.iter().fold(0, |a, &t| a + t) / {count},
+        {times}.iter().min().unwrap(),
+        {times}.iter().max().unwrap());
+}};"#
+            , black_box = self.internal_name("_rusti_black_box")
+            , times = self.internal_name("_rusti_times")
+            , count = count
+            , expr = expr);
+
+        match parse_program(code.as_slice(), false, None) {
+            Program(input) => { let _ = self.run_input(input, ExprMode::Print, false); }
+            _ => ()
+        }
+    }
+
+    fn cd_command(&mut self, dir: &str) {
+        let path = self.cwd.join(dir);
+
+        if !path.is_dir() {
+            println!("{} is not a directory", dir);
+            return;
+        }
+
+        self.cwd = path;
+    }
+
+    fn crate_command(&mut self, path: String) {
+        let dir = self.cwd.join(path.as_slice());
+
+        if !dir.is_dir() {
+            println!("{} is not a directory", path);
+            return;
+        }
+
+        let dir = dir.display().to_string();
+
+        self.libs.push(dir.clone());
+        self.engine.add_search_path(dir);
+    }
+
+    fn debug_command(&mut self, args: Option<String>) {
+        match args.as_ref().map(|s| s.as_slice()) {
+            Some("on") => self.debug = true,
+            Some("off") => self.debug = false,
+            Some(args) => println!("invalid argument `{}`; expected `on` or `off`", args),
+            None => println!("debug: {}", if self.debug { "on" } else { "off" }),
+        }
+    }
+
+    fn def_command(&self, name: &str) {
+        if !self.defined_names().iter().any(|n| n.as_slice() == name) {
+            println!("no definition found for `{}`", name);
+            return;
+        }
+
+        let mut items = self.items.iter()
+            .filter(|i| i.name.as_ref().map(|n| n.as_slice()) == Some(name))
+            .collect::<Vec<_>>();
+
+        // A type definition is shown first, followed by inherent impls,
+        // then trait impls. Sorting is stable, so each group remains
+        // in the order it was defined.
+        items.sort_by(|a, b| def_order(a.kind).cmp(&def_order(b.kind)));
+
+        for item in items.iter() {
+            println!("{}", item.source);
+        }
+
+        // Imports and bindings follow items
+        for &(ref names, src) in self.definitions().iter() {
+            if names.iter().any(|n| n.as_slice() == name) &&
+                    !items.iter().any(|i| i.source.as_slice() == src) {
+                println!("{}", src);
+            }
+        }
+
+        for s in self.statics.iter().filter(|s| s.name.as_slice() == name) {
+            println!("let {}: &mut {};", s.name, s.ty);
+        }
+    }
+
+    fn doc_command(&self, path: &str) {
+        if is_ident(path) && self.items.iter()
+                .any(|i| i.name.as_ref().map(|n| n.as_slice()) == Some(path)) {
+            println!("`{}` is defined in this session; no documentation is available",
+                path);
+            return;
+        }
+
+        // Names which are not paths are looked up in the prelude
+        let full_path = if path.contains("::") {
+            path.to_string()
+        } else {
+            format!("std::prelude::{}", path)
+        };
+
+        let alias = self.internal_name("_rusti_doc");
+        let prog = self.build_program(None,
+            format!("use {} as {};", full_path, alias).as_slice());
+
+        let res = self.engine.with_analysis(prog,
+            move |analysis| find_doc_path(alias.as_slice(), analysis));
+
+        match res {
+            Ok(Ok((item_path, kind))) => println!("{}",
+                doc_url(self.engine.sysroot(), item_path.as_slice(), kind)),
+            Ok(Err(msg)) => println!("{}", msg),
+            Err(AnalysisError::Panicked(msg)) => println!("analysis failed: {}", msg),
+            Err(AnalysisError::CompileError) => ()
+        }
+    }
+
+    fn edit_command(&mut self) {
+        let editor = match os::getenv("EDITOR") {
+            Some(editor) => editor,
+            None => {
+                let editor = if cfg!(windows) { "notepad" } else { "vi" };
+                println!("`EDITOR` is not set; using `{}`", editor);
+                editor.to_string()
+            }
+        };
+
+        let dir = match TempDir::new("rusti") {
+            Ok(dir) => dir,
+            Err(e) => {
+                println!("failed to create temporary directory: {}", e);
+                return;
+            }
+        };
+
+        let path = dir.path().join("session.rs");
+
+        if let Err(e) = File::create(&path).write_str(self.session_source().as_slice()) {
+            println!("failed to write {}: {}", path.display(), e);
+            return;
+        }
+
+        let status = Command::new(editor.as_slice()).arg(&path)
+            .stdin(InheritFd(0)).stdout(InheritFd(1)).stderr(InheritFd(2))
+            .status();
+
+        match status {
+            Ok(ref status) if status.success() => (),
+            Ok(status) => {
+                println!("`{}` exited with {}; session is unchanged", editor, status);
+                return;
+            }
+            Err(e) => {
+                println!("failed to run `{}`: {}", editor, e);
+                return;
+            }
+        }
+
+        let code = match File::open(&path).read_to_string() {
+            Ok(code) => code,
+            Err(e) => {
+                println!("failed to read {}: {}", path.display(), e);
+                return;
+            }
+        };
+
+        let ok = match parse_program(code.as_slice(), false, path.as_str()) {
+            Program(input) => self.replace_session(input),
+            InputError(Some(e)) => {
+                println!("{}", e);
+                false
+            }
+            _ => false
+        };
+
+        if !ok {
+            println!("session is unchanged");
+        }
+    }
+
+    fn env_command(&self) {
+        fn list(items: &[String]) -> String {
+            if items.is_empty() {
+                "none".to_string()
+            } else {
+                items.connect(" ")
+            }
+        }
+
+        println!("sysroot: {}", self.engine.sysroot().display());
+        println!("library paths: {}", list(self.engine.lib_paths()));
+        println!("cfg flags: {}", list(self.engine.cfgs()));
+    }
+
+    fn gc_command(&mut self) {
+        match self.engine.remove_finished_modules() {
+            0 => println!("no modules removed"),
+            1 => println!("removed 1 module"),
+            n => println!("removed {} modules", n),
+        }
+    }
+
+    fn get_command(&mut self, name: &str) {
+        if !is_ident(name) {
+            println!("command `get` expects the name of a static");
+            return;
+        }
+
+        // A static in a module which is still loaded, as one that is pinned,
+        // holds the value assigned by code run in that module.
+        if self.engine.get_global(name).is_some() {
+            self.print_global(name);
+            return;
+        }
+
+        // Otherwise, session items are compiled again to hold the static
+        // while its value is read.
+        let prog = self.build_program(None, "");
+        let llmod = match self.engine.add_module(prog) {
+            Some(m) => m,
+            None => return
+        };
+
+        self.print_global(name);
+
+        let _ = self.engine.remove_module(llmod);
+    }
+
+    /// Prints the value of a global, using its type in the session.
+    fn print_global(&mut self, name: &str) {
+        let addr = match self.engine.get_global(name) {
+            Some(p) => p as uint,
+            None => {
+                println!("no global named `{}` found", name);
+                return;
+            }
+        };
+
+        // The static is borrowed so that a value which is not `Copy`
+        // is not moved out of it.
+        let fn_name = self.internal_name("_rusti_type");
+        let prog = self.build_program(None, format!(
+            "fn {}() {{\n{{ unsafe {{ &{} }} }};\n}}", fn_name, name).as_slice());
+
+        let ty = match self.expr_type(fn_name.as_slice(), prog, true) {
+            Some(ty) => {
+                let ty = ty.as_slice().trim_left_matches('&');
+                if ty.starts_with("'static ") {
+                    ty.slice_from("'static ".len()).to_string()
+                } else {
+                    ty.to_string()
+                }
+            }
+            None => {
+                println!("could not determine the type of `{}`", name);
+                return;
+            }
+        };
+
+        match parse_program(format!("unsafe {{ &*({}u as *const {}) }}",
+                addr, ty).as_slice(), false, None) {
+            Program(input) => { let _ = self.run_input(input, ExprMode::Print, false); }
+            _ => ()
+        }
+    }
+
+    fn help_command(&self, args: Option<String>) {
+        match args {
+            Some(name) => {
+                match lookup_command(name.as_slice()) {
+                    CommandLookup::Found(cmd) => {
+                        for &(n, args, desc) in COMMANDS.iter() {
+                            if n == cmd {
+                                print_command_help(n, args, desc);
+                            }
+                        }
+                    }
+                    CommandLookup::Ambiguous(cmds) =>
+                        print_ambiguous(name.as_slice(), cmds.as_slice()),
+                    CommandLookup::NotFound => println!("unrecognized command `{}`", name),
+                }
+            }
+            None => {
+                for &(name, args, desc) in COMMANDS.iter() {
+                    print_command_help(name, args, desc);
+                }
+            }
+        }
+    }
+
+    fn history_command(&mut self, args: Option<String>) {
+        let reader = match self.reader {
+            Some(ref mut reader) => reader,
+            None => {
+                println!("history is available only when running interactively");
+                return;
+            }
+        };
+
+        let count = match args {
+            None => HISTORY_COUNT,
+            Some(ref arg) if arg.as_slice() == "clear" => {
+                reader.clear_history();
+                return;
+            }
+            Some(ref arg) => match arg.as_slice().parse::<uint>() {
+                Some(n) => n,
+                None => {
+                    println!("invalid argument `{}`; expected a number or `clear`", arg);
+                    return;
+                }
+            }
+        };
+
+        let history = reader.history();
+        let start = history.len() - min(count, history.len());
+
+        for (i, line) in history.iter().enumerate().skip(start) {
+            println!("{:>4} {}", i + 1, line);
+        }
+    }
+
+    fn import_command(&mut self, path: &str) {
+        let path = path.trim().trim_right_matches(';').trim_right();
+        let code = format!("use {};", path);
+
+        match parse_program_quiet(code.as_slice(), None) {
+            Program(mut input) => {
+                // Anything other than a single `use` means the path
+                // contained more than a path.
+                if input.view_items.len() == 1 && input.attributes.is_empty() &&
+                        input.items.is_empty() && input.statements.is_empty() {
+                    // Glob imports are feature gated
+                    if path.ends_with("*") && !self.attributes.iter()
+                            .any(|a| a.as_slice().contains("globs")) {
+                        input.attributes.push("#![feature(globs)]".to_string());
+                    }
+                    self.handle_input(input);
+                    return;
+                }
+            }
+            _ => ()
+        }
+
+        println!("invalid import path `{}`", path);
+    }
+
+    fn let_command(&mut self, args: &str) {
+        let (name, ty, expr) = match parse_let(args) {
+            Some((_, _, None)) => {
+                println!("command `let` expects `<name> = <expr>`");
+                return;
+            }
+            Some((name, ty, Some(expr))) => (name, ty, expr),
+            None => return
+        };
+        let name = name.as_slice();
+        let expr = expr.as_slice();
+
+        if !is_ident(name) {
+            println!("command `let` expects `<name> = <expr>`");
+            return;
+        }
+
+        // Without a new type, an existing binding is assigned a new value
+        if ty.is_none() && self.statics.iter().any(|s| s.name == name) {
+            self.eval_print(format!("*{} = {};", name, expr).as_slice());
+            return;
+        }
+
+        let ty = match ty {
+            Some(ty) => ty,
+            None => {
+                let fn_name = self.internal_name("_rusti_type");
+                let prog = self.build_program(None,
+                    format!("fn {}() {{\n{{ {} }};\n}}", fn_name, expr).as_slice());
+
+                let ty = match self.expr_type(fn_name.as_slice(), prog, false) {
+                    Some(ty) => ty,
+                    None => return
+                };
+
+                // The type is found as the compiler names it, which may not be
+                // valid source; e.g. a path into a crate which is not imported.
+                let alias = self.internal_name("_rusti_let_type");
+                let prog = self.build_program(None,
+                    format!("type {} = {};", alias, ty).as_slice());
+
+                if self.engine.with_analysis_quiet(prog, |_| ()).is_err() {
+                    println!("type `{}` of the expression cannot be written in source; \
+                        give the type, as in `.let {}: <type> = {}`", ty, name, expr);
+                    return;
+                }
+
+                ty
+            }
+        };
+
+        // A binding defined again is held in a new static
+        self.run_count += 1;
+        let sym = self.internal_name(
+            format!("_rusti_let_{}_{}", name, self.run_count).as_slice());
+        let prog = self.build_program(None, format!(
+            "#[no_mangle]\npub static mut {}: {} = {};", sym, ty, expr).as_slice());
+
+        // The module holding the static is never marked finished,
+        // so it will not be removed.
+        if self.engine.add_module(prog).is_none() {
+            return;
+        }
+
+        let snapshot = self.snapshot();
+
+        if self.engine.get_global(sym.as_slice()).is_none() {
+            println!("static `{}` not found in compiled program", sym);
+            return;
+        }
+
+        self.undo_stack.push(snapshot);
+
+        self.statics.retain(|s| s.name != name);
+        self.statics.push(StaticBinding{
+            name: name.to_string(),
+            ty: ty,
+            sym: sym,
+        });
+    }
+
+    /// Applies a lint level attribute, e.g. `allow`, to the next input.
+    fn lint_level_command(&mut self, level: &str, args: &str) {
+        let lints = args.split(|c: char| c == ',' || c.is_whitespace())
+            .filter(|s| !s.is_empty()).collect::<Vec<_>>();
+
+        if lints.is_empty() {
+            println!("command `{}` expects a lint name", level);
+            return;
+        }
+
+        if let Some(lint) = lints.iter().find(|l| !is_ident(**l)) {
+            println!("invalid lint name `{}`", lint);
+            return;
+        }
+
+        self.transient_attrs.push(format!("#![{}({})]", level, lints.connect(", ")));
+    }
+
+    fn lints_command(&mut self, args: Option<String>) {
+        match args {
+            Some(lint) => {
+                if !is_ident(lint.as_slice()) {
+                    println!("invalid lint name `{}`", lint);
+                    return;
+                }
+
+                match self.allowed_lints.iter().position(|l| *l == lint) {
+                    Some(i) => { self.allowed_lints.remove(i); }
+                    None => self.allowed_lints.push(lint),
+                }
+            }
+            None => {
+                if self.allowed_lints.is_empty() {
+                    println!("no lints allowed");
+                } else {
+                    println!("allowed lints: {}", self.allowed_lints.connect(" "));
+                }
+            }
+        }
+    }
+
+    fn load_command(&mut self, name: &str) {
+        let path = self.cwd.join(name);
+
+        let code = match File::open(&path).read_to_string() {
+            Ok(code) => code,
+            Err(e) => {
+                println!("failed to read {}: {}", name, e);
+                return;
+            }
+        };
+
+        match parse_program(code.as_slice(), false, path.as_str()) {
+            Program(input) => { self.handle_input(input); }
+            InputError(Some(e)) => println!("{}", e),
+            _ => ()
+        }
+    }
+
+    fn load_expr_command(&mut self, path: &str) {
+        self.stmt_mode = Some(StmtMode::Expr);
+        self.load_command(path);
+        self.stmt_mode = None;
+    }
+
+    fn cfg_command(&mut self, args: Option<String>) {
+        let mut cfgs = self.engine.cfgs().to_vec();
+
+        match args {
+            Some(flag) => {
+                if !is_cfg_flag(flag.as_slice()) {
+                    println!("invalid cfg flag `{}`; expected `name` or `name=\"value\"`",
+                        flag);
+                    return;
+                }
+
+                match cfgs.iter().position(|c| *c == flag) {
+                    Some(i) => { cfgs.remove(i); }
+                    None => cfgs.push(flag),
+                }
+
+                self.engine.set_cfgs(cfgs);
+            }
+            None => {
+                if cfgs.is_empty() {
+                    println!("no cfg flags set");
+                } else {
+                    println!("cfg flags: {}", cfgs.connect(" "));
+                }
+            }
+        }
+    }
+
+    fn opt_command(&mut self, args: Option<String>) {
+        match args {
+            Some(args) => {
+                let level = match args.as_slice() {
+                    "0" => OptLevel::No,
+                    "1" => OptLevel::Less,
+                    "2" => OptLevel::Default,
+                    "3" => OptLevel::Aggressive,
+                    _ => {
+                        println!("invalid optimization level `{}`; expected 0-3", args);
+                        return;
+                    }
+                };
+
+                self.engine.set_opt_level(level);
+            }
+            None => {
+                let level = match self.engine.opt_level() {
+                    OptLevel::No => 0u,
+                    OptLevel::Less => 1,
+                    OptLevel::Default => 2,
+                    OptLevel::Aggressive => 3,
+                };
+
+                println!("optimization level: {}", level);
+            }
+        }
+    }
+
+    fn pin_command(&mut self, args: Option<String>) {
+        self.pin_next = true;
+
+        if let Some(args) = args {
+            match parse_program(args.as_slice(), false, None) {
+                Program(input) => { self.handle_input(input); }
+                _ => self.pin_next = false,
+            }
+        }
+    }
+
+    fn print_command(&self) {
+        print!("{}", self.build_program(None,
+            run_wrapper(self.entry_name().as_slice(),
+                self.internal_name("_rusti_run").as_slice(),
+                self.internal_name("_rusti_inner").as_slice(), "None").as_slice()));
+    }
+
+    fn reload_command(&mut self) {
+        let prog = self.build_program(None, "");
+
+        let llmod = match self.engine.add_module(prog) {
+            Some(llmod) => llmod,
+            None => {
+                println!("reload failed; session is unchanged");
+                return;
+            }
+        };
+
+        // The new module is not yet finished, so it survives the removal
+        // of older modules. It is marked finished afterward so that a later
+        // `.gc` or `.reload` can remove it in turn.
+        let n = self.engine.remove_finished_modules();
+        self.engine.set_finished(llmod);
+
+        match n {
+            1 => println!("session reloaded; removed 1 module"),
+            n => println!("session reloaded; removed {} modules", n),
+        }
+    }
+
+    /// Runs again an entry of input history, given by its number
+    /// or, as `!`, the most recent entry.
+    fn rerun_command(&mut self, entry: &str) {
+        let line = {
+            let history = match self.reader {
+                Some(ref reader) => reader.history(),
+                None => {
+                    println!("history is available only when running interactively");
+                    return;
+                }
+            };
+
+            // The last entry is the line which invoked this command.
+            // Other history commands are never run again, so that
+            // they cannot recurse.
+            let prev = if history.is_empty() { history } else { history.init() };
+
+            let line = if entry == "!" {
+                match prev.iter().rev().find(|l| !l.as_slice().starts_with(".!")) {
+                    Some(line) => line,
+                    None => {
+                        println!("no history entry to run");
+                        return;
+                    }
+                }
+            } else {
+                match entry.parse::<uint>() {
+                    Some(n) if n >= 1 && n <= prev.len() => &prev[n - 1],
+                    Some(n) => {
+                        println!("no history entry {}", n);
+                        return;
+                    }
+                    None => {
+                        println!("invalid history entry `{}`; expected a number or `!`", entry);
+                        return;
+                    }
+                }
+            };
+
+            if line.as_slice().starts_with(".!") {
+                println!("history entry `{}` cannot be run again", line);
+                return;
+            }
+
+            line.clone()
+        };
+
+        println!("{}", line);
+
+        match parse_input(line.as_slice()) {
+            Command(name, args) => self.handle_command(name, args),
+            Program(input) => { self.handle_input(input); }
+            InputError(Some(e)) => println!("{}", e),
+            _ => ()
+        }
+    }
+
+    fn reset_command(&mut self, args: Option<String>) {
+        let args = match args {
+            Some(args) => args,
+            None => {
+                self.reset();
+                return;
+            }
+        };
+
+        match args.as_slice() {
+            "attrs" => self.attributes.clear(),
+            "bindings" => {
+                self.bindings.clear();
+                self.statics.clear();
+            }
+            "imports" => self.view_items.clear(),
+            "items" => {
+                let names = self.items.iter().filter_map(|i| i.name.clone()).collect();
+                self.items.clear();
+                self.release_base();
+                self.remove_dependent_bindings(names);
+            }
+            _ => {
+                println!("unrecognized reset category `{}`; \
+                    expected `attrs`, `bindings`, `imports`, or `items`", args);
+                return;
+            }
+        }
+
+        // Snapshots refer to lengths of state which have been cleared
+        self.undo_stack.clear();
+    }
+
+    /// Removes bindings which refer to any of the given names, which are no
+    /// longer defined, and in turn those which refer to removed bindings.
+    fn remove_dependent_bindings(&mut self, mut names: Vec<String>) {
+        let mut removed = Vec::new();
+
+        // `.let` bindings refer to other names only in their types
+        for s in self.statics.iter() {
+            if mentions_any(s.ty.as_slice(), names.as_slice()) {
+                removed.push(s.name.clone());
+            }
+        }
+
+        self.statics.retain(|s| !removed.contains(&s.name));
+        names.extend(removed.iter().map(|n| n.clone()));
+
+        let bindings = replace(&mut self.bindings, Vec::new());
+
+        for b in bindings.into_iter() {
+            if mentions_any(b.source.as_slice(), names.as_slice()) {
+                names.extend(b.names.iter().map(|n| n.clone()));
+                removed.extend(b.names.into_iter());
+            } else {
+                self.bindings.push(b);
+            }
+        }
+
+        if !removed.is_empty() {
+            println!("removed bindings which depend on removed items: `{}`",
+                removed.connect("`, `"));
+        }
+    }
+
+    fn rm_import_command(&mut self, name: &str) {
+        let path = format!("use {};", name.trim_right_matches(';'));
+
+        if is_ident(name) && !self.defined_names().iter().any(|n| n.as_slice() == name) {
+            println!("no import of `{}` found", name);
+            return;
+        }
+
+        let n = self.view_items.len();
+
+        self.view_items.retain(|&(_, ref src)|
+            !view_item_names(src.as_slice()).iter().any(|n| n.as_slice() == name) &&
+            normalize_source(src.as_slice()) != path);
+
+        if self.view_items.len() == n {
+            println!("no import of `{}` found", name);
+        } else {
+            // Snapshots refer to lengths of state which have been changed
+            self.undo_stack.clear();
+        }
+    }
+
+    fn run_fn_command(&mut self, args: &str) {
+        let name = args.trim();
+        let name = if name.ends_with("()") { name.slice_to(name.len() - 2) } else { name };
+
+        let sig = self.items.iter().find(|i| i.kind == ItemKind::Fn &&
+            i.name.as_ref().map(|n| n.as_slice()) == Some(name))
+            .and_then(|item| item.fn_sig.clone());
+
+        let sig = match sig {
+            Some(sig) => sig,
+            None => {
+                println!("no function named `{}` is defined", name);
+                return;
+            }
+        };
+
+        if sig.takes_args {
+            println!("function `{}` takes arguments; call it directly instead", name);
+            return;
+        }
+
+        let call = match sig.fn_style {
+            ast::UnsafeFn => format!("unsafe {{ {}() }}", name),
+            ast::NormalFn => format!("{}()", name),
+        };
+
+        // A function without a return type would print `()`
+        let code = if sig.returns_value {
+            call
+        } else {
+            format!("{};", call)
+        };
+
+        match parse_program(code.as_slice(), false, None) {
+            Program(input) => { self.handle_input(input); }
+            _ => ()
+        }
+    }
+
+    fn save_command(&self, name: &str, force: bool) {
+        let path = self.cwd.join(name);
+
+        if path.exists() && !force {
+            println!("{} already exists; use `.save!` to overwrite it", name);
+            return;
+        }
+
+        let prog = self.build_program(None, "");
+
+        if let Err(e) = File::create(&path).write_str(prog.as_slice()) {
+            println!("failed to write {}: {}", name, e);
+        }
+    }
+
+    /// Returns text surrounded by escape sequences setting the given color,
+    /// if output is in color. Otherwise, the text is returned unchanged.
+    fn paint(&self, color: &str, s: &str) -> String {
+        if self.color && !s.is_empty() {
+            format!("{}{}{}", color, s, RESET_COLOR)
+        } else {
+            s.to_string()
+        }
+    }
+
+    /// Returns the name of a function generated by rusti.
+    fn internal_name(&self, name: &str) -> String {
+        format!("{}_{}", name, self.symbol_suffix)
+    }
+
+    /// Prints to `stderr` the time spent in each phase of compilation
+    /// recorded by the engine since traces were last printed.
+    fn print_traces(&self) {
+        let mut err = stderr();
+
+        for trace in self.engine.take_traces().into_iter() {
+            let _ = writeln!(err, "trace: {}", trace.what);
+
+            for &(phase, time) in trace.phases.iter() {
+                let _ = match time {
+                    Some(d) => writeln!(err, "  {}: {}", phase, format_duration(d)),
+                    None => writeln!(err, "  {}: did not finish", phase),
+                };
+            }
+        }
+    }
+
+    /// Returns the symbol name of the entry point of the most recently
+    /// compiled input. The name is not a valid identifier, so it cannot
+    /// conflict with any symbol defined by user code.
+    fn entry_name(&self) -> String {
+        format!("rusti entry {}.{}", self.symbol_suffix, self.run_count)
+    }
+
+    /// Returns how the value of the expression `expr` following the statements
+    /// of an input is printed, as determined by analysis of its type.
+    ///
+    /// If the input fails to compile, the value is formatted with `{}`,
+    /// so that compiling the input reports the error.
+    fn print_format(&self, input: &Input, expr: &str) -> PrintFormat {
+        let fn_name = self.internal_name("_rusti_type");
+        let traits_fn = self.internal_name("_rusti_traits");
+
+        // Format traits are imported into a function of their own,
+        // in which analysis finds them.
+        let imports = PRINT_TRAITS.iter().enumerate()
+            .map(|(i, &(path, _))| format!("use {} as {}_{};", path, traits_fn, i))
+            .collect::<Vec<_>>().connect("\n");
+
+        let prog = self.build_program(Some(input), format!(
+r#"
+fn {traits_fn}() {{
+{imports}
+}}
+
+#[allow(unused_variables, unused_mut)]
+fn {name}() {{
+{body}
+}}
+"#
+            , traits_fn = traits_fn
+            , imports = imports
+            , name = fn_name
+            , body = self.fn_body(format!("{}\n{{ ({}) }};",
+                input.statements.connect("\n"), expr).as_slice())
+            ).as_slice());
+
+        let res = self.engine.with_analysis_quiet(prog,
+            move |analysis| find_print_format(fn_name, traits_fn, analysis));
+
+        match res {
+            Ok(Some(fmt)) => fmt,
+            _ => PrintFormat::Format(PRINT_TRAITS[0].1),
+        }
+    }
+
+    /// Returns the type of the final expression in the function `fn_name`.
+    /// If `quiet` is true, compiler diagnostics are not printed.
+    fn expr_type(&self, fn_name: &str, prog: String, quiet: bool) -> Option<String> {
+        self.expr_types(fn_name, 1, prog, quiet).ok()
+            .and_then(|tys| tys.into_iter().next()).and_then(|ty| ty)
+    }
+
+    /// Returns the types of the final `count` expression statements in the
+    /// function `fn_name`. If `quiet` is true, compiler diagnostics are
+    /// not printed.
+    fn expr_types(&self, fn_name: &str, count: uint, prog: String, quiet: bool)
+            -> Result<Vec<Option<String>>, AnalysisError> {
+        let fn_name = fn_name.to_string();
+
+        if quiet {
+            self.engine.with_analysis_quiet(prog,
+                move |analysis| find_expr_types(fn_name, count, analysis))
+        } else {
+            self.engine.with_analysis(prog,
+                move |analysis| find_expr_types(fn_name, count, analysis))
+        }
+    }
+
+    fn search_command(&self, text: &str) {
+        let needle = to_lowercase(text);
+        let mut found = false;
+
+        for (i, &(_, src)) in self.definitions().iter().enumerate() {
+            if to_lowercase(src).contains(needle.as_slice()) {
+                println!("{}: {}", i, src);
+                found = true;
+            }
+        }
+
+        if !found {
+            println!("no imports, items, or bindings contain `{}`", text);
+        }
+    }
+
+    /// Runs the given code with a forced classification or, without code,
+    /// applies the classification to the next input.
+    fn stmt_mode_command(&mut self, mode: StmtMode, args: Option<String>) {
+        self.stmt_mode = Some(mode);
+
+        if let Some(args) = args {
+            match parse_program(args.as_slice(), false, None) {
+                Program(input) => { self.handle_input(input); }
+                _ => self.stmt_mode = None,
+            }
+        }
+    }
+
+    fn time_command(&mut self, code: &str) {
+        match parse_program(code, false, None) {
+            Program(input) => { let _ = self.run_input(input, ExprMode::Print, true); }
+            _ => ()
+        }
+    }
+
+    /// Returns whether a persistent binding with the given name exists.
+    fn has_binding(&self, name: &str) -> bool {
+        self.bindings.iter().any(|b| b.names.iter().any(|n| *n == name))
+    }
+
+    /// Returns the declared type of the named persistent binding.
+    fn binding_type(&self, name: &str) -> Option<String> {
+        let fn_name = self.internal_name("_rusti_type");
+        let prog = self.build_fn_program(None, fn_name.as_slice(), "");
+
+        let name = name.to_string();
+
+        self.engine.with_analysis(prog, move |analysis| {
+            let mut v = BindingType{
+                fn_name: fn_name,
+                name: name,
+                result: None,
+                ty_cx: &analysis.ty_cx,
+            };
+
+            visit::walk_crate(&mut v, analysis.ty_cx.map.krate());
+
+            v.result
+        }).ok().and_then(|ty| ty)
+    }
+
+    /// Returns the signature of the named function defined in the session;
+    /// e.g. `fn compute(x: int) -> int`.
+    fn fn_signature_type(&self, name: &str) -> Option<String> {
+        let prog = self.build_program(None, "");
+        let name = name.to_string();
+
+        self.engine.with_analysis_quiet(prog, move |analysis| {
+            let mut v = FnSignature{
+                fn_name: name,
+                result: None,
+                ty_cx: &analysis.ty_cx,
+            };
+
+            visit::walk_crate(&mut v, analysis.ty_cx.map.krate());
+
+            v.result
+        }).ok().and_then(|sig| sig)
+    }
+
+    fn timeout_command(&mut self, args: Option<String>) {
+        match args {
+            Some(ref args) if args.as_slice() == "off" => self.timeout = None,
+            Some(args) => match parse_duration(args.as_slice()) {
+                Some(d) if d > Duration::zero() => self.timeout = Some(d),
+                _ => println!("invalid timeout `{}`; expected e.g. `5s`, `500ms`, or `off`",
+                    args),
+            },
+            None => match self.timeout {
+                Some(d) => println!("timeout: {}", format_duration(d)),
+                None => println!("timeout: off"),
+            }
+        }
+    }
+
+    fn trace_command(&mut self, args: Option<String>) {
+        match args.as_ref().map(|s| s.as_slice()) {
+            Some("on") => self.engine.set_trace(true),
+            Some("off") => self.engine.set_trace(false),
+            Some(args) => println!("invalid argument `{}`; expected `on` or `off`", args),
+            None => println!("trace: {}", if self.engine.trace() { "on" } else { "off" }),
+        }
+    }
+
+    fn type_command(&mut self, args: String) {
+        let name = args.as_slice().trim();
+
+        if is_ident(name) && self.has_binding(name) {
+            if let Some(ty) = self.binding_type(name) {
+                println!("{} = {}", name, ty);
+            }
+            return;
+        }
+
+        // A `.let` binding is reported with its declared type,
+        // rather than as a reference to its `static mut`
+        if let Some(s) = self.statics.iter().find(|s| s.name == name) {
+            println!("{} = {}", name, s.ty);
+            return;
+        }
+
+        if is_ident(name) && self.items.iter().any(|i| i.kind == ItemKind::Fn &&
+                i.name.as_ref().map(|n| n.as_slice()) == Some(name)) {
+            match self.fn_signature_type(name) {
+                Some(sig) => println!("{}", sig),
+                None => println!("could not determine the signature of `{}`", name),
+            }
+            return;
+        }
+
+        let exprs = match parse_exprs(args.as_slice()) {
+            Some(exprs) => exprs,
+            None => return,
+        };
+
+        let name = self.internal_name("_rusti_type");
+        let prog = self.build_fn_program(None, name.as_slice(), exprs.iter()
+            .map(|e| format!("{{ {} }};", e)).collect::<Vec<_>>().connect("\n").as_slice());
+
+        match self.expr_types(name.as_slice(), exprs.len(), prog, false) {
+            Ok(tys) => {
+                for (expr, ty) in exprs.iter().zip(tys.iter()) {
+                    match *ty {
+                        Some(ref ty) => println!("{} = {}", expr, ty),
+                        None => println!("could not determine the type of `{}`", expr),
+                    }
+                }
+            }
+            Err(AnalysisError::Panicked(msg)) => println!("type analysis failed: {}", msg),
+            // Compiler diagnostics explain the failure
+            Err(AnalysisError::CompileError) => (),
+        }
+    }
+
+    fn version_command(&self) {
+        println!("rusti {}", version());
+
+        match self.engine.rustc_version() {
+            Some(v) => println!("{}", v),
+            None => println!("rustc version unknown"),
+        }
+    }
+
+    fn which_command(&self, name: &str) {
+        let count = self.engine.module_count();
+        let found = [("function", self.engine.function_module(name)),
+                     ("global", self.engine.global_module(name))];
+        let mut any = false;
+
+        for &(kind, module) in found.iter() {
+            if let Some(i) = module {
+                println!("{} `{}` found in module {} of {}", kind, name, i, count);
+                any = true;
+            }
+        }
+
+        if !any {
+            println!("no function or global named `{}` found", name);
+        }
+    }
+}
+
+/// Returns code defining the function `name`, which calls the function `inner`
+/// to run the given statements and catches any panic. The statements are
+/// expected to evaluate to `Option<Box<Any + Send>>`, which is stored in
+/// the argument to `name`.
+///
+/// The function `name` is exported under the symbol `entry`,
+/// by which the host finds it.
+fn run_wrapper(entry: &str, name: &str, inner: &str, stmts: &str) -> String {
+    format!(
+r#"
+#[export_name = "{entry}"]
+pub fn {name}(value: &mut Option<Box<::std::any::Any + Send>>) -> Option<String> {{
+    use std::any::AnyRefExt;
+
+    match unsafe {{ std::rt::unwind::try(|| *value = {inner}()) }} {{
+        Ok(()) => None,
+        Err(e) => Some(
+            if let Some(s) = e.downcast_ref::<&'static str>() {{
+                s.to_string()
+            }} else if let Some(s) = e.downcast_ref::<String>() {{
+                s.clone()
+            }} else {{
+                "Box<Any>".to_string()
+            }}),
+    }}
+}}
+
+#[allow(unused_variables, unused_mut)]
+fn {inner}() -> Option<Box<::std::any::Any + Send>> {{
+{stmts}
+}}
+"#
+        , entry = entry
+        , name = name
+        , inner = inner
+        , stmts = stmts)
+}
+
+/// Returns whether the given string is a valid argument to `rustc --cfg`;
+/// either an identifier or an identifier followed by `="value"`.
+fn is_cfg_flag(s: &str) -> bool {
+    match s.find('=') {
+        Some(i) => {
+            let value = s.slice_from(i + 1);
+
+            is_ident(s.slice_to(i)) && value.len() >= 2 &&
+                value.starts_with("\"") && value.ends_with("\"") &&
+                !value.slice(1, value.len() - 1).contains_char('"')
+        }
+        None => is_ident(s),
+    }
+}
+
+/// Returns whether the item `new` replaces the earlier item `old`;
+/// i.e. both are the same kind of item, with the same name.
+/// `impl` blocks and macro invocations are never replaced.
+fn redefines(new: &Item, old: &Item) -> bool {
+    match new.kind {
+        ItemKind::Impl | ItemKind::TraitImpl |
+            ItemKind::Macro | ItemKind::ExternBlock => false,
+        kind => kind == old.kind && new.name.is_some() && new.name == old.name,
+    }
+}
+
+/// Returns the position of an item in the output of `.def`;
+/// type definitions precede inherent impls, which precede trait impls.
+fn def_order(kind: ItemKind) -> uint {
+    match kind {
+        ItemKind::Impl => 1,
+        ItemKind::TraitImpl => 2,
+        _ => 0,
+    }
+}
+
+/// Changes the classification of the final statement of an input.
+/// Returns `false` if the final statement cannot be an expression.
+fn force_stmt_mode(input: &mut Input, mode: StmtMode) -> bool {
+    match mode {
+        StmtMode::Stmt => input.last_expr = false,
+        StmtMode::Expr => {
+            let is_binding = match input.statements.last() {
+                Some(last) => input.bindings.iter().any(|b| b.source == *last),
+                None => return true,
+            };
+
+            if is_binding {
+                println!("final statement is a `let` declaration, not an expression");
+                return false;
+            }
+
+            let last = input.statements.last_mut().unwrap();
+            let len = last.as_slice().trim_right().trim_right_matches(';').len();
+
+            last.truncate(len);
+            input.last_expr = true;
+        }
+    }
+
+    true
+}
+
+/// Sorts `extern crate` declarations before `use` declarations.
+/// `extern crate` declarations with attributes, such as those importing
+/// macros, are sorted before all others.
+///
+/// The relative order of view items of the same kind is preserved,
+/// as glob and specific imports may shadow one another depending on order.
+fn sort_view_items(vitems: Vec<(ViewItem, &str)>) -> Vec<(ViewItem, &str)> {
+    let (externs, uses) = vitems.partition(|&(vi, _)| vi == ViewItem::ExternCrate);
+    let (mut sorted, plain) = externs.partition(|&(_, s)| s.trim_left().starts_with("#"));
+
+    sorted.extend(plain.into_iter());
+    sorted.extend(uses.into_iter());
+    sorted
+}
+
+/// Returns whether source code contains any of the given names as a word.
+fn mentions_any(src: &str, names: &[String]) -> bool {
+    src.split(|c: char| !(c.is_alphanumeric() || c == '_'))
+        .any(|word| names.iter().any(|n| n.as_slice() == word))
+}
+
+/// Returns source code with each run of whitespace replaced by a single space.
+fn normalize_source(s: &str) -> String {
+    s.words().collect::<Vec<_>>().connect(" ")
+}
+
+/// Returns the string with all characters converted to lowercase.
+fn to_lowercase(s: &str) -> String {
+    s.chars().map(|c| c.to_lowercase()).collect()
+}
+
+/// Returns whether the string is a valid identifier.
+fn is_ident(s: &str) -> bool {
+    let mut chars = s.chars();
+
+    match chars.next() {
+        Some(c) if c.is_alphabetic() || c == '_' =>
+            chars.all(|c| c.is_alphanumeric() || c == '_'),
+        _ => false
+    }
+}
+
+/// Returns whether the codes of compiler diagnostics indicate that names
+/// imported by `use` declarations conflict with one another or with
+/// other names.
+fn is_import_conflict(codes: &[String]) -> bool {
+    codes.iter().any(|code| IMPORT_CONFLICT_CODES.contains(&code.as_slice()))
+}
+
+/// Formats a duration as a number of milliseconds.
+fn format_duration(d: Duration) -> String {
+    match d.num_microseconds() {
+        Some(us) => format!("{}.{:03} ms", us / 1000, us % 1000),
+        None => format!("{} ms", d.num_milliseconds()),
+    }
+}
+
+/// Parses a duration given in seconds, e.g. `5` or `5s`, or milliseconds,
+/// e.g. `500ms`.
+fn parse_duration(s: &str) -> Option<Duration> {
+    if s.ends_with("ms") {
+        s.slice_to(s.len() - 2).parse::<i64>().map(Duration::milliseconds)
+    } else {
+        s.trim_right_matches('s').parse::<i64>().map(Duration::seconds)
+    }
+}
+
+/// Runs the compiled program function `f` in a new thread, waiting at most
+/// `limit` for it to finish. Returns the value and panic message set by `f`,
+/// or `None` if it did not finish in time.
+///
+/// Output written by the thread is forwarded to this thread's `stdout` and
+/// `stderr` while waiting. After a timeout, the thread is abandoned;
+/// further output from it causes it to panic, but code which writes no
+/// output may continue to run in the background.
+fn run_with_timeout(f: fn(&mut Option<Box<Any + Send>>) -> Option<String>,
+        limit: Duration) -> Option<(Option<Box<Any + Send>>, Option<String>)> {
+    let (tx, rx) = channel();
+    let (out_tx, out_rx) = channel();
+    let (err_tx, err_rx) = channel();
+
+    let thread = Builder::new()
+        .stdout(box ChanWriter::new(out_tx))
+        .stderr(box ChanWriter::new(err_tx))
+        .spawn(move || {
+            let mut value = None;
+            let msg = f(&mut value);
+            // The receiver is gone if the program timed out
+            let _ = tx.send_opt((value, msg));
+        });
+
+    // Waited on in place of a timeout if a timer cannot be created
+    let (_never_tx, never_rx) = channel();
+    let mut timer = Timer::new();
+
+    let timeout = match timer {
+        Ok(ref mut timer) => timer.oneshot(limit),
+        Err(ref e) => {
+            debug!("failed to create timer: {}", e);
+            never_rx
+        }
+    };
+
+    loop {
+        select! {
+            res = rx.recv_opt() => {
+                // All output is sent before the result
+                loop {
+                    match out_rx.try_recv() {
+                        Ok(data) => forward_output(data, false),
+                        Err(_) => break,
+                    }
+                }
+                loop {
+                    match err_rx.try_recv() {
+                        Ok(data) => forward_output(data, true),
+                        Err(_) => break,
+                    }
+                }
+                // The thread has finished once joined, so it is not
+                // mistaken for one spawned by the program
+                let _ = thread.join();
+                return res.ok();
+            },
+            data = out_rx.recv_opt() => if let Ok(data) = data {
+                forward_output(data, false);
+            },
+            data = err_rx.recv_opt() => if let Ok(data) = data {
+                forward_output(data, true);
+            },
+            _ = timeout.recv_opt() => {
+                thread.detach();
+                return None;
+            }
+        }
+    }
+}
+
+/// Writes output from a thread run by `run_with_timeout` to `stderr`,
+/// if `err` is `true`, or otherwise to this thread's `stdout`.
+fn forward_output(data: Vec<u8>, err: bool) {
+    if err {
+        let _ = stderr().write(data.as_slice());
+    } else {
+        print!("{}", String::from_utf8_lossy(data.as_slice()));
+    }
+}
+
+/// Returns the number of threads in this process, if it can be found.
+/// A thread spawned by program code which is still running after
+/// the program returns is found by an increase in this number.
+fn thread_count() -> Option<uint> {
+    if cfg!(target_os = "linux") {
+        readdir(&Path::new("/proc/self/task")).ok().map(|tasks| tasks.len())
+    } else {
+        None
+    }
+}
+
+/// Returns the types of the final `count` expression statements in the
+/// function `fn_name`. If a statement is not an expression statement or
+/// its type cannot be found, its element will be `None`.
+fn find_expr_types(fn_name: String, count: uint, analysis: &ty::CrateAnalysis)
+        -> Vec<Option<String>> {
+    let mut v = ExprType{
+        fn_name: fn_name,
+        count: count,
+        result: Vec::new(),
+        ty_cx: &analysis.ty_cx,
+    };
+
+    visit::walk_crate(&mut v, analysis.ty_cx.map.krate());
+
+    v.result
+}
+
+/// Returns how the final expression statement of the function `fn_name`
+/// is printed, using the format traits imported in the function `traits_fn`,
+/// or `None` if the type of the expression is not found.
+fn find_print_format(fn_name: String, traits_fn: String, analysis: &ty::CrateAnalysis)
+        -> Option<PrintFormat> {
+    let tcx = &analysis.ty_cx;
+
+    let mut v = ExprFormat{
+        fn_name: fn_name,
+        traits_fn: traits_fn,
+        traits: Vec::new(),
+        diverges: false,
+        result: None,
+        ty_cx: tcx,
+    };
+
+    visit::walk_crate(&mut v, tcx.map.krate());
+
+    // A diverging expression has no value to print
+    if v.diverges {
+        return Some(PrintFormat::Type("!".to_string()));
+    }
+
+    let ty = match v.result {
+        Some(ty) => ty,
+        None => return None,
+    };
+
+    for (&(_, fmt), &did) in PRINT_TRAITS.iter().zip(v.traits.iter()) {
+        if implements_trait(tcx, ty, did) {
+            return Some(PrintFormat::Format(fmt));
+        }
+    }
+
+    Some(PrintFormat::Type(ty.repr(tcx)))
+}
+
+/// Returns whether a type implements the trait `trait_did`.
+fn implements_trait<'tcx>(tcx: &ty::ctxt<'tcx>, ty: ty::Ty<'tcx>,
+        trait_did: ast::DefId) -> bool {
+    let trait_ref = Rc::new(ty::TraitRef{
+        def_id: trait_did,
+        substs: tcx.mk_substs(Substs::new_trait(Vec::new(), Vec::new(), ty)),
+    });
+
+    let obligation = traits::Obligation::new(traits::ObligationCause::dummy(),
+        ty::Binder(trait_ref).as_predicate());
+
+    let infcx = infer::new_infer_ctxt(tcx);
+    let param_env = ty::empty_parameter_environment(tcx);
+    let mut selcx = traits::SelectionContext::new(&infcx, &param_env);
+
+    selcx.evaluate_obligation(&obligation)
+}
+
+/// Finds the item imported with the given alias name.
+/// Returns the path of the item, beginning with its crate name,
+/// and the kind of documentation page describing it.
+fn find_doc_path(alias: &str, analysis: &ty::CrateAnalysis)
+        -> Result<(Vec<String>, &'static str), String> {
+    let tcx = &analysis.ty_cx;
+
+    let id = tcx.map.krate().module.view_items.iter().filter_map(|vi| {
+        match vi.node {
+            ViewItemUse(ref vp) => match vp.node {
+                ViewPathSimple(ident, _, id)
+                    if token::get_ident(ident).get() == alias => Some(id),
+                _ => None
+            },
+            _ => None
+        }
+    }).next();
+
+    let def = match id.and_then(|id| tcx.def_map.borrow().get(&id).cloned()) {
+        Some(def) => def,
+        None => return Err("item could not be resolved".to_string()),
+    };
+
+    let (did, kind) = match def {
+        def::DefFn(did, _) => (did, "fn"),
+        def::DefStruct(did) => (did, "struct"),
+        def::DefTy(did, true) => (did, "enum"),
+        def::DefTy(did, false) => (did, "type"),
+        def::DefTrait(did) => (did, "trait"),
+        def::DefStatic(did, _) => (did, "static"),
+        def::DefConst(did) => (did, "constant"),
+        def::DefMod(did) => (did, "mod"),
+        _ => return Err("no documentation page exists for this kind of item".to_string()),
+    };
+
+    if did.krate == ast::LOCAL_CRATE {
+        return Err("item is defined in this session; \
+            no documentation is available".to_string());
+    }
+
+    let path = ty::item_path_str(tcx, did);
+
+    Ok((path.as_slice().split_str("::").map(|s| s.to_string()).collect(), kind))
+}
+
+/// Returns the URL of the rustdoc page for an item.
+/// Documentation installed in the sysroot is used, if it is found.
+fn doc_url(sysroot: &Path, path: &[String], kind: &str) -> String {
+    let local = sysroot.join_many(&["share", "doc", "rust", "html"]);
+
+    let base = if local.join(path[0].as_slice()).is_dir() {
+        format!("file://{}", local.display())
+    } else {
+        "http://doc.rust-lang.org".to_string()
+    };
+
+    let name = path.last().unwrap();
+    let parents = path.init().connect("/");
+
+    if kind == "mod" {
+        format!("{}/{}/{}/index.html", base, parents, name)
+    } else {
+        format!("{}/{}/{}.{}.html", base, parents, kind, name)
+    }
+}
+
+struct ExprType<'a, 'tcx: 'a> {
     fn_name: String,
-    result: Option<String>,
+    /// Number of statements, from the end of the function, to examine
+    count: uint,
+    result: Vec<Option<String>>,
     ty_cx: &'a ty::ctxt<'tcx>,
 }
 
@@ -355,11 +3356,171 @@ impl<'v, 'a, 'tcx> visit::Visitor<'v> for ExprType<'a, 'tcx> {
             b: &'v ast::Block, _s: codemap::Span, _n: ast::NodeId) {
         if let visit::FkItemFn(ident, _, _, _) = fk {
             if token::get_ident(ident).get() == self.fn_name {
-                if let Some(ref stmt) = b.stmts.last() {
+                let n = b.stmts.len();
+                let start = n - min(n, self.count);
+
+                for stmt in b.stmts.slice_from(start).iter() {
+                    let ty = match stmt.node {
+                        // A diverging expression has no type of its own
+                        StmtSemi(ref expr, _) if expr_diverges(self.ty_cx, &**expr) =>
+                            Some("!".to_string()),
+                        StmtSemi(ref expr, _) => self.ty_cx.node_types.borrow()
+                            .get(&expr.id).map(|ty| ty.repr(self.ty_cx)),
+                        _ => None,
+                    };
+
+                    self.result.push(ty);
+                }
+            }
+        }
+    }
+}
+
+/// Finds the type of the final expression statement in the function `fn_name`
+/// and the traits imported, in order, in the function `traits_fn`
+struct ExprFormat<'a, 'tcx: 'a> {
+    fn_name: String,
+    traits_fn: String,
+    traits: Vec<ast::DefId>,
+    /// Whether the expression never produces a value
+    diverges: bool,
+    result: Option<ty::Ty<'tcx>>,
+    ty_cx: &'a ty::ctxt<'tcx>,
+}
+
+impl<'v, 'a, 'tcx> visit::Visitor<'v> for ExprFormat<'a, 'tcx> {
+    fn visit_fn(&mut self, fk: visit::FnKind<'v>, _fd: &'v ast::FnDecl,
+            b: &'v ast::Block, _s: codemap::Span, _n: ast::NodeId) {
+        if let visit::FkItemFn(ident, _, _, _) = fk {
+            let name = token::get_ident(ident);
+
+            if name.get() == self.traits_fn {
+                for vi in b.view_items.iter() {
+                    if let ViewItemUse(ref vp) = vi.node {
+                        if let ViewPathSimple(_, _, id) = vp.node {
+                            if let Some(def::DefTrait(did)) =
+                                    self.ty_cx.def_map.borrow().get(&id).cloned() {
+                                self.traits.push(did);
+                            }
+                        }
+                    }
+                }
+            } else if name.get() == self.fn_name {
+                if let Some(stmt) = b.stmts.last() {
                     if let StmtSemi(ref expr, _) = stmt.node {
-                        let id = expr.id;
-                        if let Some(ty) = self.ty_cx.node_types.borrow().get(&id) {
-                            self.result = Some(ty.repr(self.ty_cx));
+                        if expr_diverges(self.ty_cx, &**expr) {
+                            self.diverges = true;
+                        } else {
+                            self.result = self.ty_cx.node_types.borrow()
+                                .get(&expr.id).map(|&ty| ty);
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Returns whether an expression never produces a value; e.g. `return`,
+/// `break`, or a call to a function returning `!`, such as `panic!()`.
+fn expr_diverges(tcx: &ty::ctxt, expr: &ast::Expr) -> bool {
+    match expr.node {
+        ExprRet(_) | ExprBreak(_) | ExprAgain(_) => true,
+        ExprParen(ref e) => expr_diverges(tcx, &**e),
+        ExprBlock(ref b) => match b.expr {
+            Some(ref e) => expr_diverges(tcx, &**e),
+            None => b.stmts.last().map_or(false, |stmt| match stmt.node {
+                StmtSemi(ref e, _) => expr_diverges(tcx, &**e),
+                _ => false,
+            }),
+        },
+        ExprCall(ref f, _) => tcx.node_types.borrow().get(&f.id)
+            .map_or(false, |&fty| fn_diverges(fty)),
+        ExprMethodCall(..) => tcx.method_map.borrow()
+            .get(&ty::MethodCall::expr(expr.id))
+            .map_or(false, |m| fn_diverges(m.ty)),
+        _ => false,
+    }
+}
+
+/// Returns whether a function type has the return type `!`.
+fn fn_diverges<'tcx>(fty: ty::Ty<'tcx>) -> bool {
+    match fty.sty {
+        ty::ty_bare_fn(..) | ty::ty_closure(..) => match ty::ty_fn_ret(fty) {
+            ty::FnDiverging => true,
+            ty::FnConverging(_) => false,
+        },
+        _ => false,
+    }
+}
+
+/// Finds the signature of the module-level function `fn_name`
+struct FnSignature<'a, 'tcx: 'a> {
+    fn_name: String,
+    result: Option<String>,
+    ty_cx: &'a ty::ctxt<'tcx>,
+}
+
+impl<'v, 'a, 'tcx> visit::Visitor<'v> for FnSignature<'a, 'tcx> {
+    fn visit_fn(&mut self, fk: visit::FnKind<'v>, fd: &'v ast::FnDecl,
+            _b: &'v ast::Block, _s: codemap::Span, id: ast::NodeId) {
+        if let visit::FkItemFn(ident, _, fn_style, abi) = fk {
+            if token::get_ident(ident).get() == self.fn_name {
+                let fty = ty::node_id_to_type(self.ty_cx, id);
+
+                let params = fd.inputs.iter().zip(ty::ty_fn_args(fty).iter())
+                    .map(|(arg, ty)| format!("{}: {}",
+                        pprust::pat_to_string(&*arg.pat), ty.repr(self.ty_cx)))
+                    .collect::<Vec<_>>().connect(", ");
+
+                let ret = match ty::ty_fn_ret(fty) {
+                    ty::FnConverging(ty) if ty::type_is_nil(ty) => String::new(),
+                    ty::FnConverging(ty) => format!(" -> {}", ty.repr(self.ty_cx)),
+                    ty::FnDiverging => " -> !".to_string(),
+                };
+
+                let unsafety = match fn_style {
+                    ast::UnsafeFn => "unsafe ",
+                    ast::NormalFn => "",
+                };
+
+                let abi = match abi {
+                    abi::Rust => String::new(),
+                    abi => format!("extern {} ", abi),
+                };
+
+                self.result = Some(format!("{}{}fn {}({}){}",
+                    unsafety, abi, self.fn_name, params, ret));
+            }
+        }
+    }
+}
+
+/// Finds the type of the last binding of a name in the function `fn_name`
+struct BindingType<'a, 'tcx: 'a> {
+    fn_name: String,
+    name: String,
+    result: Option<String>,
+    ty_cx: &'a ty::ctxt<'tcx>,
+}
+
+impl<'v, 'a, 'tcx> visit::Visitor<'v> for BindingType<'a, 'tcx> {
+    fn visit_fn(&mut self, fk: visit::FnKind<'v>, _fd: &'v ast::FnDecl,
+            b: &'v ast::Block, _s: codemap::Span, _n: ast::NodeId) {
+        if let visit::FkItemFn(ident, _, _, _) = fk {
+            if token::get_ident(ident).get() == self.fn_name {
+                for stmt in b.stmts.iter() {
+                    if let StmtDecl(ref decl, _) = stmt.node {
+                        if let DeclLocal(ref local) = decl.node {
+                            walk_pat(&*local.pat, |p| {
+                                if let PatIdent(_, ref ident, _) = p.node {
+                                    if token::get_ident(ident.node).get() == self.name {
+                                        self.result = self.ty_cx.node_types.borrow()
+                                            .get(&p.id).map(|ty| ty.repr(self.ty_cx));
+                                    }
+                                }
+                                true
+                            });
                         }
                     }
                 }
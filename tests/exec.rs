@@ -2,7 +2,7 @@ extern crate rusti;
 
 use std::mem::transmute;
 
-use rusti::exec::ExecutionEngine;
+use rusti::exec::{ExecutionEngine, ModuleInfo, RemoveError};
 
 fn new_ee(code: &str) -> ExecutionEngine {
     ExecutionEngine::new_with_input(code, Vec::new())
@@ -19,12 +19,23 @@ pub fn hello() -> int {
 }
 "#);
 
-    let f: fn() -> int = unsafe { transmute(ee.get_function("hello")
-        .expect("could not get fn hello")) };
+    let f: fn() -> int = unsafe { ee.get_fn("hello") }
+        .expect("could not get fn hello");
 
     assert_eq!(f(), 123);
 }
 
+#[ignore]
+#[test]
+fn test_try_new() {
+    assert!(ExecutionEngine::try_new_with_input("fn foo() {}", Vec::new()).is_ok());
+
+    match ExecutionEngine::try_new_with_input("fn foo() { bar() }", Vec::new()) {
+        Ok(_) => panic!("invalid input compiled successfully"),
+        Err(e) => assert!(e.contains("unresolved name `bar`")),
+    }
+}
+
 #[ignore]
 #[test]
 fn test_static() {
@@ -44,8 +55,10 @@ pub fn get_foo() -> int {
 
     assert_eq!(unsafe { *foo_var }, 12345);
 
-    let foo_fn: fn() -> int = unsafe { transmute(ee.get_function("get_foo")
-        .expect("could not get fn get_foo")) };
+    assert_eq!(unsafe { ee.get_global_value::<int>("FOO") }, Some(12345));
+
+    let foo_fn: fn() -> int = unsafe { ee.get_fn("get_foo") }
+        .expect("could not get fn get_foo");
 
     assert_eq!(foo_fn(), 12345);
 }
@@ -68,10 +81,10 @@ pub fn get_foo() -> int {
 }
 "#);
 
-    let get: fn() -> int = unsafe { transmute(ee.get_function("get_foo")
-        .expect("could not get fn get_foo")) };
-    let set: fn(int) = unsafe { transmute(ee.get_function("set_foo")
-        .expect("could not get fn set_foo")) };
+    let get: fn() -> int = unsafe { ee.get_fn("get_foo") }
+        .expect("could not get fn get_foo");
+    let set: fn(int) = unsafe { ee.get_fn("set_foo") }
+        .expect("could not get fn set_foo");
 
     assert_eq!(get(), 1);
 
@@ -98,8 +111,8 @@ pub fn thread_local() -> int {
 }
 "#);
     
-    let f: fn() -> int = unsafe { transmute(ee.get_function("thread_local")
-        .expect("could not get fn thread_local")) };
+    let f: fn() -> int = unsafe { ee.get_fn("thread_local") }
+        .expect("could not get fn thread_local");
 
     assert_eq!(f(), 123);
 }
@@ -115,8 +128,135 @@ pub fn thread_spawn() {
 }
 "#);
 
-    let f: fn() = unsafe { transmute(ee.get_function("thread_spawn")
-        .expect("could not get fn thread_spawn")) };
+    let f: fn() = unsafe { ee.get_fn("thread_spawn") }
+        .expect("could not get fn thread_spawn");
 
     f();
 }
+
+#[ignore]
+#[test]
+fn test_module_limit() {
+    let mut ee = ExecutionEngine::new(Vec::new());
+
+    ee.set_module_limit(Some(2));
+
+    let m = ee.add_module("#[no_mangle] pub fn one() -> int { 1 }")
+        .expect("failed to compile module");
+    ee.set_finished(m);
+
+    ee.add_module("#[no_mangle] pub fn two() -> int { 2 }")
+        .expect("failed to compile module");
+
+    let one: Option<fn() -> int> = unsafe { ee.get_fn("one") };
+    let two: Option<fn() -> int> = unsafe { ee.get_fn("two") };
+
+    assert!(one.is_none());
+    assert_eq!(two.map(|f| f()), Some(2));
+}
+
+#[ignore]
+#[test]
+fn test_remove_module() {
+    let mut ee = ExecutionEngine::new(Vec::new());
+
+    let m = ee.add_module("#[no_mangle] pub fn one() -> int { 1 }")
+        .expect("failed to compile module");
+
+    assert_eq!(ee.remove_module(m), Ok(()));
+    assert_eq!(ee.remove_module(m), Err(RemoveError::NotFound));
+}
+
+#[ignore]
+#[test]
+fn test_pinned_module() {
+    let mut ee = ExecutionEngine::new(Vec::new());
+
+    let m = ee.add_module("#[no_mangle] pub fn one() -> int { 1 }")
+        .expect("failed to compile module");
+    ee.set_finished(m);
+    ee.set_pinned(m, true);
+
+    assert_eq!(ee.remove_finished_modules(), 0);
+
+    let one: Option<fn() -> int> = unsafe { ee.get_fn("one") };
+    assert_eq!(one.map(|f| f()), Some(1));
+}
+
+#[ignore]
+#[test]
+fn test_module_info() {
+    let mut ee = ExecutionEngine::new(Vec::new());
+
+    ee.add_module(r#"
+#[no_mangle] pub static FOO: int = 1;
+#[no_mangle] pub fn one() -> int { 1 }
+"#).expect("failed to compile module");
+
+    assert_eq!(ee.module_count(), 2);
+    assert!(ee.module_info(2).is_none());
+
+    let ModuleInfo{index, running, functions, globals, ..} =
+        ee.module_info(1).expect("no module at index 1");
+
+    assert_eq!(index, 1);
+    assert!(running);
+    assert!(functions.iter().any(|f| f.as_slice() == "one"));
+    assert!(globals.iter().any(|g| g.as_slice() == "FOO"));
+    assert_eq!(ee.modules().len(), 2);
+}
+
+#[ignore]
+#[test]
+fn test_analysis_capture() {
+    let ee = ExecutionEngine::new(Vec::new());
+
+    let (res, _) = ee.with_analysis_capture("fn foo() {}", |_| ());
+    assert!(res.is_ok());
+
+    let (res, msgs) = ee.with_analysis_capture("fn foo() { bar() }", |_| ());
+    assert!(res.is_err());
+    assert!(msgs.contains("unresolved name `bar`"));
+}
+
+#[ignore]
+#[test]
+fn test_bytes_input() {
+    let ee = ExecutionEngine::new(Vec::new());
+
+    assert!(ee.with_analysis_quiet(b"fn foo() {}".as_slice(), |_| ()).is_ok());
+    assert!(ee.with_analysis_quiet(b"fn foo() {}".to_vec(), |_| ()).is_ok());
+
+    let (res, msgs) = ee.with_analysis_capture(b"fn foo() { \xff }".as_slice(), |_| ());
+    assert!(res.is_err());
+    assert!(msgs.contains("input is not valid UTF-8"));
+}
+
+#[ignore]
+#[test]
+fn test_target() {
+    let mut ee = ExecutionEngine::new_with_target(Vec::new(),
+        "arm-unknown-linux-gnueabihf".to_string());
+
+    assert_eq!(ee.target(), Some("arm-unknown-linux-gnueabihf"));
+    assert!(ee.add_module("fn foo() {}").is_none());
+}
+
+#[ignore]
+#[test]
+fn test_unresolved_symbol() {
+    let mut ee = ExecutionEngine::new(Vec::new());
+
+    let (res, msgs) = ee.add_module_capture(r#"
+extern { fn rusti_missing_symbol(); }
+
+#[no_mangle]
+pub fn call_missing() {
+    unsafe { rusti_missing_symbol() }
+}
+"#);
+
+    assert!(res.is_none());
+    assert!(msgs.contains("unresolved external symbol `rusti_missing_symbol`"));
+    assert_eq!(ee.modules().len(), 1);
+}
@@ -0,0 +1,41 @@
+extern crate rusti;
+
+use rusti::input::{ends_within_literal, parse_block};
+use rusti::input::InputResult::{InputError, Program};
+
+#[test]
+fn test_ends_within_literal() {
+    assert!(ends_within_literal("let s = \"{\n"));
+    assert!(ends_within_literal("let s = r#\"}\"\n"));
+    assert!(ends_within_literal("/* {\n"));
+
+    assert!(!ends_within_literal("let s = r#\"{\"#;\n"));
+    assert!(!ends_within_literal("// {\n"));
+    assert!(!ends_within_literal("let c = '}';\n"));
+    assert!(!ends_within_literal("fn foo<'a>(s: &'a str) {\n"));
+}
+
+#[test]
+fn test_parse_block() {
+    match parse_block("let mut n = 0i;\nfor i in range(0i, 3) {\n    n += i;\n}\nn\n") {
+        Program(input) => {
+            assert_eq!(input.statements.len(), 3);
+            assert!(input.items.is_empty());
+            assert!(input.last_expr);
+        }
+        _ => panic!("failed to parse block statements")
+    }
+
+    match parse_block("fn foo() -> int {\n    1\n}\n") {
+        Program(input) => {
+            assert_eq!(input.items.len(), 1);
+            assert!(input.statements.is_empty());
+        }
+        _ => panic!("failed to parse block item")
+    }
+
+    match parse_block("for i in range(0i, 3) {\n") {
+        InputError(_) => (),
+        _ => panic!("incomplete block did not result in an error")
+    }
+}
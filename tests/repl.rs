@@ -1,14 +1,36 @@
+extern crate rusti;
+
+use std::default::Default;
 use std::io::process::Command;
 
-fn repl_run(args: &[&str]) -> String {
+use rusti::repl::{EvalResult, Repl, ReplConfig};
+
+fn rusti_cmd(args: &[&str]) -> Command {
     let rusti = if cfg!(windows) { "target/rusti.exe" } else { "target/rusti" };
 
-    match Command::new(rusti).args(args).env("HOME", "data").output() {
+    let mut cmd = Command::new(rusti);
+    cmd.args(args).env("HOME", "data");
+    cmd
+}
+
+fn repl_run(args: &[&str]) -> String {
+    match rusti_cmd(args).output() {
         Ok(out) => String::from_utf8(out.output).unwrap(),
         Err(e) => panic!("failed to spawn process: {}", e)
     }
 }
 
+fn repl_stdin(input: &str) -> String {
+    let mut p = match rusti_cmd(&["--no-rc"]).spawn() {
+        Ok(p) => p,
+        Err(e) => panic!("failed to spawn process: {}", e)
+    };
+
+    p.stdin.take().unwrap().write_str(input).unwrap();
+
+    String::from_utf8(p.wait_with_output().unwrap().output).unwrap()
+}
+
 fn repl_cmd(cmd: &str) -> String {
     repl_run(&["--no-rc", "-c", cmd])
 }
@@ -31,6 +53,68 @@ fn test_eval() {
     assert_eq!(repl_eval("fn foo() -> int { 3i }; foo()"), "3\n");
     assert_eq!(repl_eval(
         "bitflags!{ flags Flags: u32 { const A = 4 } } A.bits"), "4\n");
+    assert_eq!(repl_eval("struct Foo; Foo"), "<Foo>\n");
+}
+
+#[test]
+fn test_eval_status() {
+    let status = |code| rusti_cmd(&["--no-rc", "-e", code]).status()
+        .unwrap_or_else(|e| panic!("failed to spawn process: {}", e));
+
+    assert!(status("1i").success());
+    assert!(!status("foo()").success());
+    assert!(!status("panic!()").success());
+    // A trait error unrelated to printing is reported, not printed as a type
+    assert!(!status("fn id<T: Clone>(t: T) -> T { t } struct Foo; id(Foo)").success());
+}
+
+#[test]
+fn test_closure_block() {
+    assert_eq!(repl_eval("(|x| x + 1)(5i)"), "6\n");
+    assert_eq!(repl_eval("{ let y = 3i; y * 2 }"), "6\n");
+}
+
+#[test]
+fn test_unicode() {
+    assert_eq!(repl_eval(r#"#![feature(non_ascii_idents)]
+        fn café() -> &'static str { "naïve ☃" } café()"#), "naïve ☃\n");
+    assert_eq!(repl_cmd(r#".type "ünïcödé", 'é'"#),
+        "\"ünïcödé\" = &'static str\n'é' = char\n");
+}
+
+#[test]
+fn test_panic() {
+    assert_eq!(repl_eval(r#"panic!("oops");"#),
+        "target/rusti: evaluation panicked: oops\n");
+    assert_eq!(repl_eval(r#"panic!("{}", 1i);"#),
+        "target/rusti: evaluation panicked: 1\n");
+}
+
+#[test]
+fn test_allow() {
+    assert_eq!(repl_file("data/test_allow.rs"), "\
+4
+invalid lint name `foo-bar`
+");
+}
+
+#[test]
+fn test_crate_attributes() {
+    assert_eq!(repl_eval("#![feature(box_syntax)] let b = box 5i; *b"), "5\n");
+    assert_eq!(repl_eval("fn foo() -> int { 1 } #![feature(box_syntax)] foo()"), "1\n");
+}
+
+#[test]
+fn test_extern_crate() {
+    assert_eq!(repl_eval(r#"extern crate "collections" as coll;
+        use coll::vec::Vec; Vec::<int>::new().len()"#), "0\n");
+    assert_eq!(repl_eval(r#"use coll::vec::Vec;
+        extern crate "collections" as coll; Vec::<int>::new().len()"#), "0\n");
+}
+
+#[test]
+fn test_pub_extern_crate() {
+    assert_eq!(repl_file("data/test_pub_extern_crate.rs"), "0\n0\n");
 }
 
 #[test]
@@ -43,10 +127,564 @@ fn test_rc() {
     assert_eq!(repl_run(&["-e", r#""hi, rc!""#]), "rc says hi\nhi, rc!\n");
 }
 
+#[test]
+fn test_cargo() {
+    assert_eq!(repl_run(&["--no-rc", "--cargo", "-e", "1i + 1"]), "2\n");
+}
+
+#[test]
+fn test_cd() {
+    let out = repl_file("data/test_cd.rs");
+    let mut lines = out.as_slice().lines();
+
+    assert_eq!(lines.next(), Some("loaded"));
+    assert_eq!(lines.next(), Some("42"));
+    assert_eq!(lines.next(), Some("nonexistent is not a directory"));
+    assert!(lines.next().unwrap().ends_with("data"));
+}
+
+#[test]
+fn test_cfg() {
+    assert_eq!(repl_file("data/test_cfg.rs"), r#"no cfg flags set
+1
+cfg flags: foo feature="bar"
+cfg flags: feature="bar"
+invalid cfg flag `foo=bar`; expected `name` or `name="value"`
+"#);
+}
+
+#[test]
+fn test_config() {
+    let run = |config: &str, args: &[&str]| {
+        let out = rusti_cmd(args).env("RUSTI_CONFIG", config).output().unwrap();
+        String::from_utf8(out.output).unwrap()
+    };
+
+    assert_eq!(run("data/rusti_config.rs", &["-c", ".opt"]),
+        "config says hi\noptimization level: 2\n");
+    assert_eq!(run("data/nonexistent", &["-e", "1i"]), "1\n");
+}
+
+#[test]
+fn test_bench() {
+    assert!(repl_cmd(".bench 10 1i + 1").starts_with("10 iterations; mean: "));
+    assert_eq!(repl_cmd(".bench 0 1i"), "iteration count must be greater than zero\n");
+}
+
+#[test]
+fn test_crate() {
+    assert_eq!(repl_cmd(".crate data/nonexistent"),
+        "data/nonexistent is not a directory\n");
+}
+
+#[test]
+fn test_debug() {
+    let out = repl_file("data/test_debug.rs");
+    let head = "debug: off\n";
+    let tail = "\
+debug: on
+invalid argument `yes`; expected `on` or `off`
+debug: on
+";
+
+    assert!(out.starts_with(head));
+    assert!(out.ends_with(tail));
+
+    // The program is shown only for the input which failed with `.debug on`
+    let listing = out.as_slice().slice(head.len(), out.len() - tail.len());
+
+    assert!(listing.starts_with("   1 "));
+    assert!(listing.contains("undefined_name"));
+}
+
+#[test]
+fn test_def() {
+    assert_eq!(repl_file("data/test_def.rs"), "\
+fn foo() -> int { 1 }
+struct Bar;
+impl Bar {
+    fn bar(&self) {}
+}
+impl Clone for Bar {
+    fn clone(&self) -> Bar { Bar }
+}
+no definition found for `baz`
+");
+}
+
+#[test]
+fn test_env() {
+    let out = repl_run(&["--no-rc", "-L", "data", "-c", ".env"]);
+    let mut lines = out.as_slice().lines();
+
+    assert!(lines.next().unwrap().starts_with("sysroot: "));
+    assert_eq!(lines.next(), Some("library paths: data"));
+    assert_eq!(lines.next(), Some("cfg flags: none"));
+}
+
+#[test]
+fn test_gc() {
+    assert_eq!(repl_cmd(".gc"), "no modules removed\n");
+
+    // A module compiled by `.reload` is finished and may be removed
+    let out = repl_file("data/test_gc.rs");
+    let lines = out.as_slice().lines().collect::<Vec<_>>();
+
+    assert_eq!(lines.len(), 4);
+    assert_eq!(lines[0], "session reloaded; removed 0 modules");
+    assert_eq!(lines[1], "removed 1 module");
+    assert_eq!(lines[2], "no modules removed");
+    assert_eq!(lines[3], "1");
+}
+
+#[test]
+fn test_get() {
+    assert_eq!(repl_file("data/test_get.rs"), "\
+no global named `FOO` found
+12345
+[1, 2, 3]
+5
+");
+}
+
+#[test]
+fn test_help() {
+    assert_eq!(repl_cmd(".help type"),
+        ".type <expr>         Display the type of an expression\n");
+    assert_eq!(repl_cmd(".h b"),
+        ".block               Run multiple lines of Rust code; end with `.` or `;;`\n");
+    assert_eq!(repl_cmd(".help foo"), "unrecognized command `foo`\n");
+    assert_eq!(repl_cmd(".help lo"),
+        "ambiguous command `lo`; could be .load, .load-expr\n");
+    assert_eq!(repl_cmd(".help load"),
+        ".load <file>         Load a Rust source file into the session\n");
+}
+
+#[test]
+fn test_ambiguous_command() {
+    assert_eq!(repl_cmd(".re"),
+        "ambiguous command `re`; could be .reload, .reset\n");
+    // `.r` is kept as an abbreviation of `.reset`
+    assert_eq!(repl_cmd(".r foo"), "unrecognized reset category `foo`; \
+        expected `attrs`, `bindings`, `imports`, or `items`\n");
+}
+
+#[test]
+fn test_let() {
+    assert_eq!(repl_file("data/test_let.rs"), "\
+a = int
+side effect
+2
+binding `b` will not persist because its initializer may have side effects
+a = int
+");
+}
+
+#[test]
+fn test_let_side_effects() {
+    assert_eq!(repl_file("data/test_let_side_effects.rs"), "\
+x = int
+called
+binding `x` will not persist because its initializer may have side effects
+called
+1
+binding `a`, `b` will not persist because its initializer may have side effects
+");
+}
+
+#[test]
+fn test_lints() {
+    let out = rusti_cmd(&["--no-rc", "data/test_lints.rs"]).output().unwrap();
+
+    assert_eq!(String::from_utf8(out.output).unwrap(), "\
+allowed lints: dead_code unused_imports
+allowed lints: dead_code
+no lints allowed
+invalid lint name `unused-imports`
+");
+
+    // A lint which is no longer allowed is reported
+    let err = String::from_utf8(out.error).unwrap();
+    assert!(err.contains("never used: `unused_fn`"));
+
+    let out = rusti_cmd(&["--no-rc", "-e", "fn unused_fn() {}"]).output().unwrap();
+    let err = String::from_utf8(out.error).unwrap();
+    assert!(!err.contains("never used"));
+}
+
+#[test]
+fn test_load() {
+    assert_eq!(repl_file("data/test_load.rs"), "loaded\n42\n");
+}
+
+#[test]
+fn test_load_expr() {
+    assert_eq!(repl_file("data/test_load_expr.rs"),
+        "42\ncommand `load-expr` expects a filename\n");
+}
+
+#[test]
+fn test_opt() {
+    assert_eq!(repl_cmd(".opt"), "optimization level: 0\n");
+    assert_eq!(repl_cmd(".opt 4"), "invalid optimization level `4`; expected 0-3\n");
+}
+
+#[test]
+fn test_pin() {
+    // Code of an input which is not pinned is removed once it is run
+    assert_eq!(repl_file("data/test_pin.rs"), "\
+no function or global named `unpinned` found
+2
+no modules removed
+function `pinned` found in module 2 of 3
+");
+}
+
+#[test]
+fn test_base_module() {
+    assert_eq!(repl_file("data/test_base.rs"), "42\ndouble(1) = int\n8\nquad(1) = int\n\
+18\n8\nhello, world\n");
+}
+
+#[test]
+fn test_redefine() {
+    assert_eq!(repl_file("data/test_redefine.rs"), "\
+1
+2
+fn foo() -> int { 2 }
+1
+");
+}
+
+#[test]
+fn test_reload() {
+    // Each reload replaces the module compiled by the one before
+    assert_eq!(repl_file("data/test_reload.rs"), "\
+session reloaded; removed 0 modules
+function `foo` found in module 1 of 2
+session reloaded; removed 1 module
+function `foo` found in module 1 of 2
+2
+");
+}
+
+#[test]
+fn test_reset() {
+    assert_eq!(repl_file("data/test_reset.rs"), "1\n2\n");
+    assert_eq!(repl_file("data/test_reset_items.rs"), "\
+removed bindings which depend on removed items: `f`, `g`\n5\n5\n\
+unrecognized reset category `foo`; expected `attrs`, `bindings`, `imports`, or `items`\n");
+}
+
+#[test]
+fn test_undo() {
+    assert_eq!(repl_file("data/test_undo.rs"), "1\n2\nnothing to undo\n");
+}
+
+#[test]
+fn test_timeout() {
+    assert_eq!(repl_file("data/test_timeout.rs"), "\
+timeout: off
+timeout: 5000.000 ms
+timeout: 250.000 ms
+timeout: off
+invalid timeout `soon`; expected e.g. `5s`, `500ms`, or `off`
+");
+}
+
+#[test]
+fn test_timeout_loop() {
+    assert_eq!(repl_file("data/test_timeout_loop.rs"), "\
+before
+target/rusti: evaluation timed out
+after
+");
+}
+
+#[test]
+fn test_run() {
+    assert_eq!(repl_file("data/test_run.rs"), "\
+42
+hello
+7
+function `add` takes arguments; call it directly instead
+no function named `missing` is defined
+");
+}
+
+#[test]
+fn test_trace() {
+    assert_eq!(repl_file("data/test_trace.rs"), "\
+trace: off
+trace: on
+1
+trace: off
+");
+
+    let out = rusti_cmd(&["--no-rc", "data/test_trace.rs"]).output().unwrap();
+    let err = String::from_utf8(out.error).unwrap();
+
+    assert!(err.contains("trace: compile\n"));
+    assert!(err.contains("  phase 1 (parse input): "));
+    assert!(err.contains("  phase 4 (translate to LLVM): "));
+}
+
 #[test]
 fn test_type() {
     assert_eq!(repl_cmd(".type 1i"), "1i = int\n");
     assert_eq!(repl_cmd(r#".t "hai2u""#), "\"hai2u\" = &'static str\n");
     assert_eq!(repl_cmd(".t &1i"), "&1i = &int\n");
     assert_eq!(repl_cmd(".t vec![1u]"), "vec![1u] = collections::vec::Vec<uint>\n");
+    assert_eq!(repl_cmd(".t 1u, 'a'"), "1u = uint\n'a' = char\n");
+    assert_eq!(repl_cmd(".t (1i, 2u), Some(1u)"),
+        "(1i, 2u) = (int, uint)\nSome(1u) = core::option::Option<uint>\n");
+}
+
+#[test]
+fn test_type_unit_diverging() {
+    assert_eq!(repl_cmd(".type ()"), "() = ()\n");
+    assert_eq!(repl_cmd(r#".type panic!("x")"#), "panic!(\"x\") = !\n");
+}
+
+#[test]
+fn test_type_fn() {
+    assert_eq!(repl_file("data/test_type_fn.rs"),
+        "fn compute(x: int, (a, b): (uint, uint)) -> int\nfn hello()\n\
+unsafe extern \"C\" fn raw() -> i32\n");
+}
+
+#[test]
+fn test_version() {
+    let out = repl_cmd(".version");
+    let mut lines = out.as_slice().lines();
+
+    assert!(lines.next().unwrap().starts_with("rusti "));
+    assert!(lines.next().unwrap().starts_with("rustc "));
+}
+
+#[test]
+fn test_which() {
+    assert_eq!(repl_file("data/test_which.rs"), "\
+no function or global named `foo` found
+function `foo` found in module 1 of 2
+");
+}
+
+#[test]
+fn test_history() {
+    assert_eq!(repl_cmd(".history"),
+        "history is available only when running interactively\n");
+    assert_eq!(repl_cmd(".!1"),
+        "history is available only when running interactively\n");
+}
+
+#[test]
+fn test_import() {
+    assert_eq!(repl_file("data/test_import.rs"), "\
+0
+true
+no definition found for `num`
+use std::collections::HashMap;
+invalid import path `foo bar`
+");
+}
+
+#[test]
+fn test_sysroot() {
+    assert_eq!(repl_run(&["--no-rc", "--sysroot", "data/nonexistent", "-e", "1i"]),
+        "target/rusti: invalid sysroot data/nonexistent: lib/rustlib not found\n");
+}
+
+#[test]
+fn test_stdin() {
+    assert_eq!(repl_stdin("fn foo() -> int {\n    1\n}\nfoo()\n"), "1\n");
+    assert_eq!(repl_stdin("let a = 2i;\n.type a\n"), "a = int\n");
+}
+
+#[test]
+fn test_ast() {
+    let out = repl_cmd(".ast use std::mem; fn foo() {} let a = 1i; a");
+
+    assert!(out.as_slice().starts_with("\
+view item: use std::mem;
+item: fn foo() {}
+statement: let a = 1i;
+expression: a
+"));
+}
+
+#[test]
+fn test_view_item_order() {
+    let out = repl_file("data/test_view_order.rs");
+    let pos = |s: &str| out.as_slice().find_str(s).unwrap();
+
+    assert!(pos("extern crate collections;") < pos("use std::mem::replace;"));
+    assert!(pos("use std::mem::replace;") < pos("use std::mem::swap;"));
+}
+
+#[test]
+fn test_view_item_dedup() {
+    let out = repl_file("data/test_view_dedup.rs");
+
+    assert_eq!(out.as_slice().match_indices("crate collections;").count(), 1);
+    assert_eq!(out.as_slice().match_indices("use std::mem::swap;").count(), 1);
+}
+
+#[test]
+fn test_import_conflict() {
+    assert_eq!(repl_file("data/test_import_conflict.rs"), "\
+this import conflicts with a previously imported name; try `.rm-import`
+no import of `replace` found
+");
+}
+
+#[test]
+fn test_classify() {
+    assert_eq!(repl_eval("if true { println!(\"yes\"); }"), "yes\n");
+    assert_eq!(repl_eval("let mut a = 0i; while a < 3 { a += 1; }"), "");
+    assert_eq!(repl_eval("if true { 1i } else { 2 }"), "1\n");
+    assert_eq!(repl_eval("macro_rules! three(() => (3i)); three!()"), "3\n");
+}
+
+#[test]
+fn test_semicolon() {
+    assert_eq!(repl_eval("5i"), "5\n");
+    assert_eq!(repl_eval("5i;"), "");
+    assert_eq!(repl_eval("vec![5i]"), "[5]\n");
+    assert_eq!(repl_eval("vec![5i];"), "");
+    assert_eq!(repl_eval("let a = 5i; a;"), "");
+}
+
+#[test]
+fn test_search() {
+    assert_eq!(repl_file("data/test_search.rs"), "\
+0: use std::collections::HashMap;
+1: fn make_map() -> HashMap<int, int> { HashMap::new() }
+no imports, items, or bindings contain `nothing`
+1: fn make_map() -> HashMap<int, int> { HashMap::new() }
+");
+}
+
+#[test]
+fn test_defined_names() {
+    assert_eq!(repl_file("data/test_defined_names.rs"), "\
+use std::mem::swap;
+fn foo() -> int { 1 }
+let a = 2i;
+no definition found for `b`
+0: use std::mem::swap;
+2: let a = 2i;
+no import of `foo` found
+no definition found for `swap`
+");
+}
+
+#[test]
+fn test_multiline_file() {
+    assert_eq!(repl_file("data/test_impl.rs"), "1\n");
+}
+
+#[test]
+fn test_doc() {
+    let out = repl_file("data/test_doc.rs");
+    let mut lines = out.as_slice().lines();
+
+    assert_eq!(lines.next(),
+        Some("`Foo` is defined in this session; no documentation is available"));
+    assert!(lines.next().unwrap().ends_with("/core/mem/fn.swap.html"));
+}
+
+#[test]
+fn test_let_static() {
+    assert_eq!(repl_file("data/test_let_static.rs"), "\
+2
+counter = int
+5
+true
+flag = bool
+true
+command `let` expects `<name> = <expr>`
+type `core::option::Option<u8>` of the expression cannot be written in source; \
+give the type, as in `.let s: <type> = Some(1u8)`
+true
+no definition found for `limit`
+");
+}
+
+#[test]
+fn test_main() {
+    assert_eq!(repl_eval(r#"fn main() { println!("main"); }"#),
+        "function `main` will not run automatically; enter `main()` to run it\n");
+    assert_eq!(repl_eval(r#"fn main() { println!("main"); } main();"#), "main\n");
+}
+
+#[test]
+fn test_macro_crate() {
+    let out = repl_file("data/test_macro_crate.rs");
+    let pos = |s: &str| out.as_slice().find_str(s).unwrap();
+
+    assert!(out.as_slice().starts_with("false\n"));
+    assert!(pos("#[phase(plugin, link)]\nextern crate log;") < pos("extern crate collections;"));
+}
+
+#[test]
+fn test_stmt_mode() {
+    assert_eq!(repl_file("data/test_stmt_mode.rs"), "3\n\
+final statement is a `let` declaration, not an expression\n4\n");
+}
+
+#[test]
+fn test_prelude() {
+    assert_eq!(repl_run(&["--no-rc", "--prelude", "data/prelude.rs",
+        "-e", "let mut m: HashMap<int, int> = prelude_map(); m.insert(1, 2); m.len()"]),
+        "1\n");
+}
+
+#[ignore]
+#[test]
+fn test_prelude_config() {
+    let config = ReplConfig{cfgs: vec!["foo".to_string()], ..Default::default()};
+    let prelude = "#[cfg(not(foo))] fn bar() -> int { missing }\n\
+                   #[cfg(foo)] fn bar() -> int { 1 }";
+
+    let mut repl = Repl::new_with_config_prelude(config, prelude).unwrap();
+
+    assert_eq!(repl.eval_capture("bar()"), "1\n");
+}
+
+#[ignore]
+#[test]
+fn test_eval_all() {
+    let mut repl = Repl::new();
+
+    // Each input may use definitions from those before it
+    assert_eq!(repl.eval_all(&["fn one() -> int { 1 }",
+        "fn two() -> int { one() + 1 }"]), Ok(()));
+
+    assert_eq!(repl.eval_all(&["fn three() -> int { two() + 1 }",
+        "fn four() -> int { nope() }",
+        "fn five() -> int { 5 }"]), Err(1));
+
+    // Inputs before the error are kept; later inputs are not evaluated
+    assert_eq!(repl.eval_capture("three()"), "3\n");
+    assert!(repl.check("four()").is_err());
+    assert!(repl.check("five()").is_err());
+
+    assert_eq!(repl.eval_all(&["fn six() -> int { 6 }", "(6i"]), Err(1));
+    assert_eq!(repl.eval("six()"), EvalResult::Success);
+}
+
+#[ignore]
+#[test]
+fn test_type_of() {
+    let mut repl = Repl::new();
+
+    assert_eq!(repl.eval("let a = 1u;"), EvalResult::Success);
+
+    assert_eq!(repl.type_of("1i + 2"), Some("int".to_string()));
+    assert_eq!(repl.type_of("a"), Some("uint".to_string()));
+    assert_eq!(repl.type_of("a + nope"), None);
+
+    repl.run_command(".let b: u8 = 2");
+    assert_eq!(repl.type_of("b"), Some("u8".to_string()));
 }